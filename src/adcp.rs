@@ -0,0 +1,237 @@
+//! ADCP, the text protocol Sony projectors accept on TCP port 53595.  Projectors have no sound
+//! to mute, so muting maps onto picture blanking.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::backend::{self, Backend};
+use crate::{input_name, parse_input, Error};
+
+const DEFAULT_PORT: u16 = 53595;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Adcp {
+    stream: BufReader<TcpStream>,
+}
+
+impl Adcp {
+    /// Connects to `[PASSWORD@]HOST[:PORT]`.  With authentication enabled the projector greets
+    /// with a random seed, answered with the SHA-256 of the seed followed by the password.
+    pub fn connect(address: &str) -> Result<Adcp, Error> {
+        let (password, host) = backend::split_password(address);
+        let stream = backend::connect(host, DEFAULT_PORT, TIMEOUT)?;
+        let mut adcp = Adcp {
+            stream: BufReader::new(stream),
+        };
+        let greeting = adcp.read_line()?;
+        if greeting != "NOKEY" {
+            let password = password.ok_or(Error::Adcp("password required"))?;
+            let digest = sha256(format!("{}{}", greeting, password).as_bytes());
+            let hex: Vec<String> = digest.iter().map(|b| format!("{:02x}", b)).collect();
+            adcp.write_line(&hex.concat())?;
+            match &adcp.read_line()?[..] {
+                "OK" => {}
+                "err_auth" => return Err(Error::Adcp("authentication failed")),
+                _ => return Err(Error::UnexpectedAnswer),
+            }
+        }
+        Ok(adcp)
+    }
+
+    fn read_line(&mut self) -> Result<String, Error> {
+        let mut line = String::new();
+        self.stream.read_line(&mut line)?;
+        if !line.ends_with('\n') {
+            return Err(Error::ShortResponse);
+        }
+        Ok(line.trim_end().to_string())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), Error> {
+        let line = format!("{}\r\n", line);
+        self.stream.get_mut().write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Sends a command and returns the response with any quotes removed.
+    fn command(&mut self, command: &str) -> Result<String, Error> {
+        self.write_line(command)?;
+        let response = self.read_line()?;
+        match &response[..] {
+            "err_cmd" => Err(Error::Adcp("unknown command")),
+            "err_val" => Err(Error::Adcp("invalid value")),
+            "err_option" => Err(Error::Adcp("invalid option")),
+            "err_inactive" => Err(Error::Adcp("not available in the current state")),
+            "err_auth" => Err(Error::Adcp("authentication failed")),
+            e if e.starts_with("err_") => Err(Error::Adcp("projector failure")),
+            _ => Ok(response.trim_matches('"').to_string()),
+        }
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        match &self.command(&format!("{} \"{}\"", name, value))?[..] {
+            "ok" => Ok(()),
+            _ => Err(Error::UnexpectedAnswer),
+        }
+    }
+
+    fn query(&mut self, name: &str) -> Result<String, Error> {
+        self.command(&format!("{} ?", name))
+    }
+}
+
+impl Backend for Adcp {
+    fn power_on(&mut self) -> Result<(), Error> {
+        self.set("power", "on")
+    }
+
+    fn power_off(&mut self) -> Result<(), Error> {
+        self.set("power", "off")
+    }
+
+    /// Starting up counts as on; cooling down and the standby modes count as off.
+    fn is_powered_on(&mut self) -> Result<bool, Error> {
+        let status = self.query("power_status")?;
+        Ok(status == "on" || status == "startup")
+    }
+
+    fn mute_set(&mut self, muted: bool) -> Result<(), Error> {
+        self.set("blank", if muted { "on" } else { "off" })
+    }
+
+    fn is_muted(&mut self) -> Result<bool, Error> {
+        match &self.query("blank")?[..] {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            _ => Err(Error::UnexpectedAnswer),
+        }
+    }
+
+    fn freeze(&mut self, frozen: bool) -> Result<(), Error> {
+        self.set("freeze", if frozen { "on" } else { "off" })
+    }
+
+    fn is_frozen(&mut self) -> Result<bool, Error> {
+        match &self.query("freeze")?[..] {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            _ => Err(Error::UnexpectedAnswer),
+        }
+    }
+
+    fn input_select(&mut self, kind: u8, number: u8) -> Result<(), Error> {
+        if kind == 0x00 {
+            return Err(Error::Unsupported("selecting the tuner"));
+        }
+        self.set("input", &input_name(kind, number))
+    }
+
+    fn get_input(&mut self) -> Result<(u8, u8), Error> {
+        parse_input(&self.query("input")?).ok_or(Error::UnexpectedAnswer)
+    }
+}
+
+/// SHA-256, as ADCP uses it to hash the password with the projector's random seed.
+fn sha256(message: &[u8]) -> [u8; 32] {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((message.len() as u64) * 8).to_be_bytes());
+
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    for chunk in padded.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0; 32];
+    for (out, s) in digest.chunks_mut(4).zip(state) {
+        out.copy_from_slice(&s.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(digest: &[u8]) -> String {
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// The examples from FIPS 180-2, appendix B, and the empty message.
+    #[test]
+    fn sha256_vectors() {
+        assert_eq!(
+            hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(&sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            hex(&sha256(&[b'a'; 1_000_000])),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
@@ -0,0 +1,996 @@
+//! The operations a connection to a display provides, independent of how it is reached.
+//! Inputs are identified by the serial protocol's `(kind, number)` pairs throughout.
+
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::adcp::Adcp;
+use crate::pjlink::Pjlink;
+use crate::rest::Rest;
+use crate::serial::Serial;
+use crate::Error;
+
+/// An installed app, as listed by `app list`.
+pub struct App {
+    pub title: String,
+    pub uri: String,
+}
+
+pub trait Backend {
+    fn power_on(&mut self) -> Result<(), Error>;
+    fn power_off(&mut self) -> Result<(), Error>;
+    fn is_powered_on(&mut self) -> Result<bool, Error>;
+
+    fn volume_up(&mut self) -> Result<(), Error> {
+        Err(Error::Unsupported("volume control"))
+    }
+
+    fn volume_down(&mut self) -> Result<(), Error> {
+        Err(Error::Unsupported("volume control"))
+    }
+
+    fn volume_set(&mut self, _volume: u8) -> Result<(), Error> {
+        Err(Error::Unsupported("volume control"))
+    }
+
+    fn get_volume(&mut self) -> Result<u8, Error> {
+        Err(Error::Unsupported("volume control"))
+    }
+
+    /// The lowest and highest volume levels, which percentages are scaled to.
+    fn volume_range(&self) -> (u8, u8) {
+        (0, 100)
+    }
+
+    /// A picture adjustment, identified by its serial protocol function.
+    fn picture(&mut self, _function: u8) -> Result<u8, Error> {
+        Err(Error::Unsupported("picture adjustment"))
+    }
+
+    fn set_picture(&mut self, _function: u8, _value: u8) -> Result<(), Error> {
+        Err(Error::Unsupported("picture adjustment"))
+    }
+
+    fn audio_output(&mut self) -> Result<String, Error> {
+        Err(Error::Unsupported("audio output selection"))
+    }
+
+    fn set_audio_output(&mut self, _output: &str) -> Result<(), Error> {
+        Err(Error::Unsupported("audio output selection"))
+    }
+
+    fn output_volume(&mut self, _output: &str) -> Result<u8, Error> {
+        Err(Error::Unsupported("per-output volume"))
+    }
+
+    fn set_output_volume(&mut self, _output: &str, _volume: u8) -> Result<(), Error> {
+        Err(Error::Unsupported("per-output volume"))
+    }
+
+    /// Toggles mute by querying it and setting the opposite rather than with a toggle command,
+    /// so the frame that changes anything is an explicit set, which is safe to send again if its
+    /// acknowledgement is lost.
+    fn mute_toggle(&mut self) -> Result<(), Error> {
+        let muted = self.is_muted()?;
+        self.mute_set(!muted)
+    }
+
+    fn mute_set(&mut self, _muted: bool) -> Result<(), Error> {
+        Err(Error::Unsupported("muting"))
+    }
+
+    fn is_muted(&mut self) -> Result<bool, Error> {
+        Err(Error::Unsupported("muting"))
+    }
+
+    /// Holds the picture on the current frame, or lets it run again.
+    fn freeze(&mut self, _frozen: bool) -> Result<(), Error> {
+        Err(Error::Unsupported("picture freeze"))
+    }
+
+    fn is_frozen(&mut self) -> Result<bool, Error> {
+        Err(Error::Unsupported("picture freeze"))
+    }
+
+    fn input_select(&mut self, _kind: u8, _number: u8) -> Result<(), Error> {
+        Err(Error::Unsupported("input select"))
+    }
+
+    fn get_input(&mut self) -> Result<(u8, u8), Error> {
+        Err(Error::Unsupported("input select"))
+    }
+
+    /// Presses a remote control key by its IRCC code.
+    fn send_code(&mut self, _code: &str) -> Result<(), Error> {
+        Err(Error::Unsupported("remote keys"))
+    }
+
+    fn apps(&mut self) -> Result<Vec<App>, Error> {
+        Err(Error::Unsupported("apps"))
+    }
+
+    fn launch_app(&mut self, _uri: &str) -> Result<(), Error> {
+        Err(Error::Unsupported("apps"))
+    }
+
+    /// Shows a web page full screen.
+    fn open_url(&mut self, _url: &str) -> Result<(), Error> {
+        Err(Error::Unsupported("opening URLs"))
+    }
+
+    /// Fills in the text field that currently has focus.
+    fn type_text(&mut self, _text: &str) -> Result<(), Error> {
+        Err(Error::Unsupported("text input"))
+    }
+
+    fn power_saving(&mut self) -> Result<String, Error> {
+        Err(Error::Unsupported("power saving"))
+    }
+
+    fn set_power_saving(&mut self, _mode: &str) -> Result<(), Error> {
+        Err(Error::Unsupported("power saving"))
+    }
+
+    fn led_mode(&mut self) -> Result<String, Error> {
+        Err(Error::Unsupported("LED indicator"))
+    }
+
+    fn set_led_mode(&mut self, _mode: &str) -> Result<(), Error> {
+        Err(Error::Unsupported("LED indicator"))
+    }
+
+    /// Identifying details such as model and serial number, as label/value pairs.
+    fn info(&mut self) -> Result<Vec<(&'static str, String)>, Error> {
+        Err(Error::Unsupported("system information"))
+    }
+
+    /// The firmware version, taken from the system information by default.
+    fn firmware(&mut self) -> Result<String, Error> {
+        self.info()?
+            .into_iter()
+            .find(|(label, _)| *label == "Firmware")
+            .map(|(_, version)| version)
+            .ok_or(Error::Unsupported("firmware version"))
+    }
+
+    /// What is on screen, as label/value pairs.
+    fn playing(&mut self) -> Result<Vec<(&'static str, String)>, Error> {
+        Err(Error::Unsupported("content information"))
+    }
+
+    /// Sends a raw serial protocol frame, returning any response data.
+    fn send_frame(&mut self, _frame: &[u8]) -> Result<Vec<u8>, Error> {
+        Err(Error::Unsupported("raw frames"))
+    }
+}
+
+/// Opens a device: `pjlink://[PASSWORD@]HOST[:PORT]` for a PJLink display on the network,
+/// `adcp://[PASSWORD@]HOST[:PORT]` for a Sony projector, `http://[PSK@]HOST[:PORT]` for a TV's
+/// REST API, `ssh://[USER@]HOST[:PORT]/PATH` for a serial port on another machine, or otherwise
+/// the path of a serial port.
+pub fn open(device: &str) -> Result<Box<dyn Backend>, Error> {
+    if let Some(address) = device.strip_prefix("http://") {
+        Ok(Box::new(Rest::new(address)))
+    } else if let Some(address) = device.strip_prefix("pjlink://") {
+        Ok(Box::new(Pjlink::connect(address)?))
+    } else if let Some(address) = device.strip_prefix("adcp://") {
+        Ok(Box::new(Adcp::connect(address)?))
+    } else {
+        Ok(Box::new(Serial::open(device)?))
+    }
+}
+
+/// The volume range a device is kept within, from its profile in the config.
+#[derive(Clone, Copy)]
+pub struct VolumeLimits {
+    pub min: u8,
+    pub max: u8,
+}
+
+/// What a device's `[devices.NAME]` profile asks of it beyond plain commands.
+#[derive(Clone, Default)]
+pub struct Profile {
+    pub volume_limits: Option<VolumeLimits>,
+    /// The input to select whenever the device is turned on, as many wake on the last one used.
+    pub power_on_input: Option<(u8, u8)>,
+    /// Other ways to reach the device, in the order to try them if it can't be reached.
+    pub fallbacks: Vec<String>,
+    /// The model to check commands against, as `--model` does.
+    pub model: Option<String>,
+}
+
+/// Wraps a backend to apply a device's profile: volume levels are clamped to its limits and
+/// steps past a limit are dropped, and turning it on selects its power-on input.  Everything
+/// else is passed straight through.
+pub struct Profiled {
+    backend: Box<dyn Backend>,
+    profile: Profile,
+}
+
+impl Profiled {
+    pub fn new(backend: Box<dyn Backend>, profile: Profile) -> Profiled {
+        Profiled { backend, profile }
+    }
+
+    fn clamp(&self, volume: u8) -> u8 {
+        let (min, max) = self.volume_range();
+        volume.clamp(min, max)
+    }
+
+    /// Selects an input once the device is on, retrying until a query confirms it since TVs
+    /// ignore commands while they boot.
+    fn select_power_on_input(&mut self, input: (u8, u8)) -> Result<(), Error> {
+        let deadline = Instant::now() + crate::POWER_ON_TIMEOUT;
+        loop {
+            if self.backend.is_powered_on().unwrap_or(false) {
+                let selected = self
+                    .backend
+                    .input_select(input.0, input.1)
+                    .and_then(|()| self.backend.get_input());
+                if selected.is_ok_and(|selected| selected == input) {
+                    return Ok(());
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout("the power-on input"));
+            }
+            thread::sleep(crate::POWER_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Backend for Profiled {
+    fn power_on(&mut self) -> Result<(), Error> {
+        self.backend.power_on()?;
+        match self.profile.power_on_input {
+            Some(input) => self.select_power_on_input(input),
+            None => Ok(()),
+        }
+    }
+
+    fn power_off(&mut self) -> Result<(), Error> {
+        self.backend.power_off()
+    }
+
+    fn is_powered_on(&mut self) -> Result<bool, Error> {
+        self.backend.is_powered_on()
+    }
+
+    fn volume_up(&mut self) -> Result<(), Error> {
+        if let Some(limits) = self.profile.volume_limits {
+            if self.backend.get_volume()? >= limits.max {
+                return Ok(());
+            }
+        }
+        self.backend.volume_up()
+    }
+
+    fn volume_down(&mut self) -> Result<(), Error> {
+        if let Some(limits) = self.profile.volume_limits {
+            if self.backend.get_volume()? <= limits.min {
+                return Ok(());
+            }
+        }
+        self.backend.volume_down()
+    }
+
+    fn volume_set(&mut self, volume: u8) -> Result<(), Error> {
+        let volume = self.clamp(volume);
+        self.backend.volume_set(volume)
+    }
+
+    fn get_volume(&mut self) -> Result<u8, Error> {
+        self.backend.get_volume()
+    }
+
+    fn volume_range(&self) -> (u8, u8) {
+        match self.profile.volume_limits {
+            Some(limits) => (limits.min, limits.max),
+            None => self.backend.volume_range(),
+        }
+    }
+
+    fn picture(&mut self, function: u8) -> Result<u8, Error> {
+        self.backend.picture(function)
+    }
+
+    fn set_picture(&mut self, function: u8, value: u8) -> Result<(), Error> {
+        self.backend.set_picture(function, value)
+    }
+
+    fn audio_output(&mut self) -> Result<String, Error> {
+        self.backend.audio_output()
+    }
+
+    fn set_audio_output(&mut self, output: &str) -> Result<(), Error> {
+        self.backend.set_audio_output(output)
+    }
+
+    fn output_volume(&mut self, output: &str) -> Result<u8, Error> {
+        self.backend.output_volume(output)
+    }
+
+    /// The limits are for the speakers, so headphones and an audio system are set as given.
+    fn set_output_volume(&mut self, output: &str, volume: u8) -> Result<(), Error> {
+        let volume = match output {
+            "speaker" => self.clamp(volume),
+            _ => volume,
+        };
+        self.backend.set_output_volume(output, volume)
+    }
+
+    fn mute_toggle(&mut self) -> Result<(), Error> {
+        self.backend.mute_toggle()
+    }
+
+    fn mute_set(&mut self, muted: bool) -> Result<(), Error> {
+        self.backend.mute_set(muted)
+    }
+
+    fn is_muted(&mut self) -> Result<bool, Error> {
+        self.backend.is_muted()
+    }
+
+    fn freeze(&mut self, frozen: bool) -> Result<(), Error> {
+        self.backend.freeze(frozen)
+    }
+
+    fn is_frozen(&mut self) -> Result<bool, Error> {
+        self.backend.is_frozen()
+    }
+
+    fn input_select(&mut self, kind: u8, number: u8) -> Result<(), Error> {
+        self.backend.input_select(kind, number)
+    }
+
+    fn get_input(&mut self) -> Result<(u8, u8), Error> {
+        self.backend.get_input()
+    }
+
+    fn send_code(&mut self, code: &str) -> Result<(), Error> {
+        self.backend.send_code(code)
+    }
+
+    fn apps(&mut self) -> Result<Vec<App>, Error> {
+        self.backend.apps()
+    }
+
+    fn launch_app(&mut self, uri: &str) -> Result<(), Error> {
+        self.backend.launch_app(uri)
+    }
+
+    fn open_url(&mut self, url: &str) -> Result<(), Error> {
+        self.backend.open_url(url)
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), Error> {
+        self.backend.type_text(text)
+    }
+
+    fn power_saving(&mut self) -> Result<String, Error> {
+        self.backend.power_saving()
+    }
+
+    fn set_power_saving(&mut self, mode: &str) -> Result<(), Error> {
+        self.backend.set_power_saving(mode)
+    }
+
+    fn led_mode(&mut self) -> Result<String, Error> {
+        self.backend.led_mode()
+    }
+
+    fn set_led_mode(&mut self, mode: &str) -> Result<(), Error> {
+        self.backend.set_led_mode(mode)
+    }
+
+    fn info(&mut self) -> Result<Vec<(&'static str, String)>, Error> {
+        self.backend.info()
+    }
+
+    fn firmware(&mut self) -> Result<String, Error> {
+        self.backend.firmware()
+    }
+
+    fn playing(&mut self) -> Result<Vec<(&'static str, String)>, Error> {
+        self.backend.playing()
+    }
+
+    fn send_frame(&mut self, frame: &[u8]) -> Result<Vec<u8>, Error> {
+        self.backend.send_frame(frame)
+    }
+}
+
+/// One of the ways to reach a device, opened when it's first needed.
+struct Transport {
+    device: String,
+    backend: Option<Box<dyn Backend>>,
+    /// Whether it has failed to open or to communicate, after which it isn't tried again.
+    failed: bool,
+}
+
+/// Whether a command can be sent again over another transport when it may already have reached
+/// the device.  Queries and commands that set an absolute value can; steps, toggles and key
+/// presses can't, since they would be carried out twice.
+#[derive(Clone, Copy, PartialEq)]
+enum Resend {
+    Safe,
+    Unsafe,
+}
+
+/// Reaches a device over whichever of its transports works: the device itself, then each of the
+/// fallbacks from its profile in turn.  A transport that can't be opened or connected to is
+/// given up on for the rest of the run and the command goes to the next one, as does a command
+/// the transport doesn't support.  A failure after a command was sent gives up on the transport
+/// too, and the command goes to the next one if it's safe to send again.  Using a fallback is
+/// reported on stderr.
+pub struct Failover {
+    transports: Vec<Transport>,
+    /// The transport that last carried out a command, so that changes can be reported.
+    used: usize,
+    /// Report on stderr as JSON objects, for `--errors json`.
+    json_errors: bool,
+}
+
+impl Failover {
+    /// Opens the first transport that can be opened.
+    pub fn open(device: &str, fallbacks: &[String], json_errors: bool) -> Result<Failover, Error> {
+        let transports = std::iter::once(device)
+            .chain(fallbacks.iter().map(String::as_str))
+            .map(|device| Transport {
+                device: device.to_string(),
+                backend: None,
+                failed: false,
+            })
+            .collect();
+        let mut failover = Failover {
+            transports,
+            used: 0,
+            json_errors,
+        };
+        failover.attempt(Resend::Safe, |_| Ok(()))?;
+        Ok(failover)
+    }
+
+    /// Runs a command over the first transport that can carry it out.
+    fn attempt<T>(
+        &mut self,
+        resend: Resend,
+        mut command: impl FnMut(&mut dyn Backend) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let json_errors = self.json_errors;
+        let mut error = None;
+        let mut tried = 0;
+        for (i, transport) in self.transports.iter_mut().enumerate() {
+            if transport.failed {
+                continue;
+            }
+            tried += 1;
+            let result = match &mut transport.backend {
+                Some(backend) => command(backend.as_mut()),
+                None => open(&transport.device)
+                    .and_then(|backend| command(transport.backend.insert(backend).as_mut())),
+            };
+            match result {
+                Err(e @ (Error::Io(_) | Error::Rest(_))) if resend == Resend::Unsafe => {
+                    transport.failed = true;
+                    return Err(Error::retried(tried - 1, e));
+                }
+                Err(e @ (Error::Open(_) | Error::Connect(_) | Error::Io(_) | Error::Rest(_))) => {
+                    let message = e.to_string();
+                    crate::report_notice(
+                        json_errors,
+                        "warning",
+                        e.kind(),
+                        Some(&transport.device),
+                        &message,
+                    );
+                    transport.failed = true;
+                    error = Some(e);
+                }
+                Err(e @ Error::Unsupported(_)) => error = Some(e),
+                Err(e) => return Err(Error::retried(tried - 1, e)),
+                Ok(value) => {
+                    if i != self.used {
+                        let message = format!("using {}", self.transports[i].device);
+                        let device = &self.transports[0].device;
+                        crate::report_notice(
+                            json_errors,
+                            "note",
+                            "fallback",
+                            Some(device),
+                            &message,
+                        );
+                        self.used = i;
+                    }
+                    return Ok(value);
+                }
+            }
+        }
+        let failed = || Error::Connect(io::Error::other("every transport has failed"));
+        Err(Error::retried(
+            tried.saturating_sub(1),
+            error.unwrap_or_else(failed),
+        ))
+    }
+}
+
+impl Backend for Failover {
+    fn power_on(&mut self) -> Result<(), Error> {
+        self.attempt(Resend::Safe, |backend| backend.power_on())
+    }
+
+    fn power_off(&mut self) -> Result<(), Error> {
+        self.attempt(Resend::Safe, |backend| backend.power_off())
+    }
+
+    fn is_powered_on(&mut self) -> Result<bool, Error> {
+        self.attempt(Resend::Safe, |backend| backend.is_powered_on())
+    }
+
+    fn volume_up(&mut self) -> Result<(), Error> {
+        self.attempt(Resend::Unsafe, |backend| backend.volume_up())
+    }
+
+    fn volume_down(&mut self) -> Result<(), Error> {
+        self.attempt(Resend::Unsafe, |backend| backend.volume_down())
+    }
+
+    fn volume_set(&mut self, volume: u8) -> Result<(), Error> {
+        self.attempt(Resend::Safe, |backend| backend.volume_set(volume))
+    }
+
+    fn get_volume(&mut self) -> Result<u8, Error> {
+        self.attempt(Resend::Safe, |backend| backend.get_volume())
+    }
+
+    fn volume_range(&self) -> (u8, u8) {
+        self.transports
+            .iter()
+            .filter(|transport| !transport.failed)
+            .find_map(|transport| transport.backend.as_ref())
+            .map_or((0, 100), |backend| backend.volume_range())
+    }
+
+    fn picture(&mut self, function: u8) -> Result<u8, Error> {
+        self.attempt(Resend::Safe, |backend| backend.picture(function))
+    }
+
+    fn set_picture(&mut self, function: u8, value: u8) -> Result<(), Error> {
+        self.attempt(Resend::Safe, |backend| backend.set_picture(function, value))
+    }
+
+    fn audio_output(&mut self) -> Result<String, Error> {
+        self.attempt(Resend::Safe, |backend| backend.audio_output())
+    }
+
+    fn set_audio_output(&mut self, output: &str) -> Result<(), Error> {
+        self.attempt(Resend::Safe, |backend| backend.set_audio_output(output))
+    }
+
+    fn output_volume(&mut self, output: &str) -> Result<u8, Error> {
+        self.attempt(Resend::Safe, |backend| backend.output_volume(output))
+    }
+
+    fn set_output_volume(&mut self, output: &str, volume: u8) -> Result<(), Error> {
+        self.attempt(Resend::Safe, |backend| {
+            backend.set_output_volume(output, volume)
+        })
+    }
+
+    fn mute_toggle(&mut self) -> Result<(), Error> {
+        self.attempt(Resend::Unsafe, |backend| backend.mute_toggle())
+    }
+
+    fn mute_set(&mut self, muted: bool) -> Result<(), Error> {
+        self.attempt(Resend::Safe, |backend| backend.mute_set(muted))
+    }
+
+    fn is_muted(&mut self) -> Result<bool, Error> {
+        self.attempt(Resend::Safe, |backend| backend.is_muted())
+    }
+
+    fn freeze(&mut self, frozen: bool) -> Result<(), Error> {
+        self.attempt(Resend::Safe, |backend| backend.freeze(frozen))
+    }
+
+    fn is_frozen(&mut self) -> Result<bool, Error> {
+        self.attempt(Resend::Safe, |backend| backend.is_frozen())
+    }
+
+    fn input_select(&mut self, kind: u8, number: u8) -> Result<(), Error> {
+        self.attempt(Resend::Safe, |backend| backend.input_select(kind, number))
+    }
+
+    fn get_input(&mut self) -> Result<(u8, u8), Error> {
+        self.attempt(Resend::Safe, |backend| backend.get_input())
+    }
+
+    fn send_code(&mut self, code: &str) -> Result<(), Error> {
+        self.attempt(Resend::Unsafe, |backend| backend.send_code(code))
+    }
+
+    fn apps(&mut self) -> Result<Vec<App>, Error> {
+        self.attempt(Resend::Safe, |backend| backend.apps())
+    }
+
+    fn launch_app(&mut self, uri: &str) -> Result<(), Error> {
+        self.attempt(Resend::Safe, |backend| backend.launch_app(uri))
+    }
+
+    fn open_url(&mut self, url: &str) -> Result<(), Error> {
+        self.attempt(Resend::Safe, |backend| backend.open_url(url))
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), Error> {
+        self.attempt(Resend::Unsafe, |backend| backend.type_text(text))
+    }
+
+    fn power_saving(&mut self) -> Result<String, Error> {
+        self.attempt(Resend::Safe, |backend| backend.power_saving())
+    }
+
+    fn set_power_saving(&mut self, mode: &str) -> Result<(), Error> {
+        self.attempt(Resend::Safe, |backend| backend.set_power_saving(mode))
+    }
+
+    fn led_mode(&mut self) -> Result<String, Error> {
+        self.attempt(Resend::Safe, |backend| backend.led_mode())
+    }
+
+    fn set_led_mode(&mut self, mode: &str) -> Result<(), Error> {
+        self.attempt(Resend::Safe, |backend| backend.set_led_mode(mode))
+    }
+
+    fn info(&mut self) -> Result<Vec<(&'static str, String)>, Error> {
+        self.attempt(Resend::Safe, |backend| backend.info())
+    }
+
+    fn firmware(&mut self) -> Result<String, Error> {
+        self.attempt(Resend::Safe, |backend| backend.firmware())
+    }
+
+    fn playing(&mut self) -> Result<Vec<(&'static str, String)>, Error> {
+        self.attempt(Resend::Safe, |backend| backend.playing())
+    }
+
+    fn send_frame(&mut self, frame: &[u8]) -> Result<Vec<u8>, Error> {
+        self.attempt(Resend::Unsafe, |backend| backend.send_frame(frame))
+    }
+}
+
+/// Splits the password off a `[PASSWORD@]HOST[:PORT]` network address.
+pub fn split_password(address: &str) -> (Option<&str>, &str) {
+    match address.rsplit_once('@') {
+        Some((password, host)) => (Some(password), host),
+        None => (None, address),
+    }
+}
+
+/// Connects to `HOST[:PORT]`, using `default_port` if none is given.
+pub fn connect(host: &str, default_port: u16, timeout: Duration) -> Result<TcpStream, Error> {
+    let addresses = if host.contains(':') {
+        host.to_socket_addrs()
+    } else {
+        (host, default_port).to_socket_addrs()
+    };
+    let address = addresses.map_err(Error::Connect)?.next().ok_or_else(|| {
+        Error::Connect(io::Error::new(io::ErrorKind::NotFound, "no address found"))
+    })?;
+    let stream = TcpStream::connect_timeout(&address, timeout).map_err(Error::Connect)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A TV that keeps its settings in memory and logs the calls it gets.
+    #[derive(Default)]
+    pub struct MockTv {
+        pub on: bool,
+        pub volume: u8,
+        pub muted: bool,
+        pub input: (u8, u8),
+        /// Whether the tuner can be selected, as it can over REST.
+        pub tuner: bool,
+        /// Whether an app is in the foreground, which leaves no input to report.
+        pub app: bool,
+        /// The calls made, shared so they can still be checked once the TV is boxed.
+        pub log: Rc<RefCell<Vec<String>>>,
+        /// What every call fails with, if anything.
+        pub failure: Option<fn() -> Error>,
+    }
+
+    impl MockTv {
+        pub fn on() -> MockTv {
+            MockTv {
+                on: true,
+                volume: 20,
+                input: (0x04, 1),
+                ..MockTv::default()
+            }
+        }
+
+        fn call(&mut self, call: String) -> Result<(), Error> {
+            self.log.borrow_mut().push(call);
+            match self.failure {
+                Some(failure) => Err(failure()),
+                None => Ok(()),
+            }
+        }
+    }
+
+    impl Backend for MockTv {
+        fn power_on(&mut self) -> Result<(), Error> {
+            self.call(String::from("power_on"))?;
+            self.on = true;
+            Ok(())
+        }
+
+        fn power_off(&mut self) -> Result<(), Error> {
+            self.call(String::from("power_off"))?;
+            self.on = false;
+            Ok(())
+        }
+
+        fn is_powered_on(&mut self) -> Result<bool, Error> {
+            self.call(String::from("is_powered_on"))?;
+            Ok(self.on)
+        }
+
+        fn volume_up(&mut self) -> Result<(), Error> {
+            self.call(String::from("volume_up"))?;
+            self.volume += 1;
+            Ok(())
+        }
+
+        fn volume_down(&mut self) -> Result<(), Error> {
+            self.call(String::from("volume_down"))?;
+            self.volume -= 1;
+            Ok(())
+        }
+
+        fn volume_set(&mut self, volume: u8) -> Result<(), Error> {
+            self.call(format!("volume_set {}", volume))?;
+            self.volume = volume;
+            Ok(())
+        }
+
+        fn get_volume(&mut self) -> Result<u8, Error> {
+            self.call(String::from("get_volume"))?;
+            Ok(self.volume)
+        }
+
+        fn set_output_volume(&mut self, output: &str, volume: u8) -> Result<(), Error> {
+            self.call(format!("set_output_volume {} {}", output, volume))
+        }
+
+        fn mute_set(&mut self, muted: bool) -> Result<(), Error> {
+            self.call(format!("mute_set {}", muted))?;
+            self.muted = muted;
+            Ok(())
+        }
+
+        fn is_muted(&mut self) -> Result<bool, Error> {
+            self.call(String::from("is_muted"))?;
+            Ok(self.muted)
+        }
+
+        fn input_select(&mut self, kind: u8, number: u8) -> Result<(), Error> {
+            if kind == 0x00 && !self.tuner {
+                return Err(Error::Unsupported("selecting the tuner"));
+            }
+            self.call(format!("input_select {:02x} {}", kind, number))?;
+            self.input = (kind, number);
+            Ok(())
+        }
+
+        fn get_input(&mut self) -> Result<(u8, u8), Error> {
+            self.call(String::from("get_input"))?;
+            if self.app {
+                return Err(Error::Unsupported("the input while an app is showing"));
+            }
+            Ok(self.input)
+        }
+    }
+
+    fn timed_out() -> Error {
+        Error::Io(io::Error::from(io::ErrorKind::TimedOut))
+    }
+
+    fn refused() -> Error {
+        Error::Connect(io::Error::from(io::ErrorKind::ConnectionRefused))
+    }
+
+    fn unsupported() -> Error {
+        Error::Unsupported("volume control")
+    }
+
+    /// A failover over mock TVs, each failing as given, with the log they share.
+    fn mock_failover(failures: &[Option<fn() -> Error>]) -> (Failover, Rc<RefCell<Vec<String>>>) {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let transports = failures
+            .iter()
+            .enumerate()
+            .map(|(i, failure)| {
+                let tv = MockTv {
+                    log: log.clone(),
+                    failure: *failure,
+                    ..MockTv::on()
+                };
+                Transport {
+                    device: format!("tv{}", i),
+                    backend: Some(Box::new(tv)),
+                    failed: false,
+                }
+            })
+            .collect();
+        let failover = Failover {
+            transports,
+            used: 0,
+            json_errors: false,
+        };
+        (failover, log)
+    }
+
+    fn failed(failover: &Failover) -> Vec<bool> {
+        failover.transports.iter().map(|t| t.failed).collect()
+    }
+
+    #[test]
+    fn failover_order() {
+        let (mut failover, log) = mock_failover(&[Some(refused), Some(refused), None, None]);
+        assert_eq!(failover.get_volume().unwrap(), 20);
+        assert_eq!(failover.used, 2);
+        assert_eq!(failed(&failover), [true, true, false, false]);
+        assert_eq!(log.borrow().len(), 3);
+        // Transports that failed aren't tried again.
+        failover.volume_set(30).unwrap();
+        assert_eq!(log.borrow().last().unwrap(), "volume_set 30");
+        assert_eq!(log.borrow().len(), 4);
+    }
+
+    #[test]
+    fn failover_exhausted() {
+        let (mut failover, _) = mock_failover(&[Some(refused), Some(refused)]);
+        match failover.is_powered_on() {
+            Err(Error::Retried(1, e)) => assert!(matches!(*e, Error::Connect(_))),
+            result => panic!("{:?}", result.map_err(|e| e.to_string())),
+        }
+        assert_eq!(failover.used, 0);
+    }
+
+    #[test]
+    fn failover_unsupported() {
+        let (mut failover, log) = mock_failover(&[Some(unsupported), None]);
+        assert_eq!(failover.get_volume().unwrap(), 20);
+        assert_eq!(failover.used, 1);
+        // The first transport still works for what it supports, so it isn't given up on.
+        assert_eq!(failed(&failover), [false, false]);
+        failover.get_volume().unwrap();
+        assert_eq!(log.borrow().len(), 4);
+
+        let (mut failover, _) = mock_failover(&[Some(unsupported), Some(unsupported)]);
+        assert!(matches!(failover.get_volume(), Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn failover_io_error() {
+        // Queries and absolute settings are sent again over the next transport.
+        let (mut failover, log) = mock_failover(&[Some(timed_out), None]);
+        assert_eq!(failover.get_volume().unwrap(), 20);
+        assert_eq!(failed(&failover), [true, false]);
+        assert_eq!(failover.used, 1);
+        assert_eq!(*log.borrow(), ["get_volume", "get_volume"]);
+
+        let (mut failover, log) = mock_failover(&[Some(timed_out), None]);
+        failover.volume_set(30).unwrap();
+        assert_eq!(*log.borrow(), ["volume_set 30", "volume_set 30"]);
+
+        // Steps aren't, as the first may have been carried out, but the next command moves on.
+        let (mut failover, log) = mock_failover(&[Some(timed_out), None]);
+        assert!(matches!(failover.volume_up(), Err(Error::Io(_))));
+        assert_eq!(*log.borrow(), ["volume_up"]);
+        assert_eq!(failed(&failover), [true, false]);
+        assert_eq!(failover.used, 0);
+        failover.volume_up().unwrap();
+        assert_eq!(*log.borrow(), ["volume_up", "volume_up"]);
+        assert_eq!(failover.used, 1);
+    }
+
+    #[test]
+    fn failover_other_errors() {
+        // An answer the transport didn't expect isn't a reason to give up on it.
+        let (mut failover, log) = mock_failover(&[Some(|| Error::UnexpectedAnswer), None]);
+        assert!(matches!(
+            failover.get_volume(),
+            Err(Error::UnexpectedAnswer)
+        ));
+        assert_eq!(failed(&failover), [false, false]);
+        assert_eq!(failover.used, 0);
+        assert_eq!(log.borrow().len(), 1);
+    }
+
+    fn profiled(limits: Option<(u8, u8)>) -> (Profiled, Rc<RefCell<Vec<String>>>) {
+        let tv = MockTv::on();
+        let log = tv.log.clone();
+        let profile = Profile {
+            volume_limits: limits.map(|(min, max)| VolumeLimits { min, max }),
+            ..Profile::default()
+        };
+        (Profiled::new(Box::new(tv), profile), log)
+    }
+
+    #[test]
+    fn profiled_volume_limits() {
+        let (mut tv, log) = profiled(Some((10, 40)));
+        tv.volume_set(80).unwrap();
+        tv.volume_set(5).unwrap();
+        assert_eq!(*log.borrow(), ["volume_set 40", "volume_set 10"]);
+        assert_eq!(tv.volume_range(), (10, 40));
+
+        // Steps past a limit are dropped.
+        tv.volume_down().unwrap();
+        assert_eq!(tv.get_volume().unwrap(), 10);
+        tv.volume_up().unwrap();
+        assert_eq!(tv.get_volume().unwrap(), 11);
+    }
+
+    #[test]
+    fn profiled_output_volume() {
+        // Only the speakers are held to the limits.
+        let (mut tv, log) = profiled(Some((10, 40)));
+        tv.set_output_volume("speaker", 80).unwrap();
+        tv.set_output_volume("headphone", 80).unwrap();
+        tv.set_output_volume("audio-system", 5).unwrap();
+        assert_eq!(
+            *log.borrow(),
+            [
+                "set_output_volume speaker 40",
+                "set_output_volume headphone 80",
+                "set_output_volume audio-system 5"
+            ]
+        );
+    }
+
+    #[test]
+    fn profiled_without_limits() {
+        let (mut tv, log) = profiled(None);
+        tv.volume_set(100).unwrap();
+        tv.volume_up().unwrap();
+        tv.volume_down().unwrap();
+        assert_eq!(
+            *log.borrow(),
+            ["volume_set 100", "volume_up", "volume_down"]
+        );
+        assert_eq!(tv.volume_range(), (0, 100));
+    }
+
+    #[test]
+    fn profiled_power_on_input() {
+        let tv = MockTv::default();
+        let log = tv.log.clone();
+        let profile = Profile {
+            power_on_input: Some((0x04, 2)),
+            ..Profile::default()
+        };
+        let mut tv = Profiled::new(Box::new(tv), profile);
+        tv.power_on().unwrap();
+        assert_eq!(
+            *log.borrow(),
+            [
+                "power_on",
+                "is_powered_on",
+                "input_select 04 2",
+                "get_input"
+            ]
+        );
+        assert_eq!(tv.get_input().unwrap(), (0x04, 2));
+    }
+}
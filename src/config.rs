@@ -0,0 +1,644 @@
+//! The optional configuration file.  It uses a small subset of TOML: `[section]` headers and
+//! `key = "value"` lines.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::backend::{Profile, VolumeLimits};
+
+/// Parses a double-quoted TOML string, allowing a trailing comment.
+fn parse_string(text: &str) -> Option<String> {
+    let mut value = String::new();
+    let mut chars = text.strip_prefix('"')?.chars();
+    loop {
+        match chars.next()? {
+            '"' => break,
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+    let rest = chars.as_str().trim();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return None;
+    }
+    Some(value)
+}
+
+/// Parses a bare TOML integer, allowing a trailing comment, as a string.
+fn parse_integer(text: &str) -> Option<String> {
+    let number = text.split('#').next().unwrap_or_default().trim();
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(number.to_string())
+}
+
+enum Token {
+    Byte(u8),
+    Param(String),
+}
+
+/// A user-defined command: a frame template such as `8c 00 20 03 {level} 00`.  The checksum is
+/// appended when the frame is sent.
+pub struct CustomCommand {
+    pub name: String,
+    template: Vec<Token>,
+}
+
+impl CustomCommand {
+    fn parse(name: &str, template: &str) -> Result<CustomCommand, String> {
+        let template = template
+            .split_whitespace()
+            .map(
+                |token| match token.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+                    Some(param) => Ok(Token::Param(param.to_string())),
+                    None => u8::from_str_radix(token, 16)
+                        .map(Token::Byte)
+                        .map_err(|_| format!("invalid byte '{}' in command '{}'", token, name)),
+                },
+            )
+            .collect::<Result<Vec<_>, _>>()?;
+        if template.len() < 3 {
+            return Err(format!("command '{}' is shorter than a frame header", name));
+        }
+        Ok(CustomCommand {
+            name: name.to_string(),
+            template,
+        })
+    }
+
+    /// Fills in the template's parameters from `NAME=VALUE` arguments, where values are decimal
+    /// or `0x`-prefixed hex bytes.
+    pub fn render(&self, args: &[String]) -> Result<Vec<u8>, String> {
+        let mut params = Vec::new();
+        for arg in args {
+            let (name, value) = arg
+                .split_once('=')
+                .ok_or_else(|| format!("expected NAME=VALUE, got '{}'", arg))?;
+            let parsed = match value.strip_prefix("0x") {
+                Some(hex) => u8::from_str_radix(hex, 16),
+                None => value.parse(),
+            };
+            let value = parsed.map_err(|_| format!("invalid byte value '{}'", value))?;
+            params.push((name, value));
+        }
+        self.template
+            .iter()
+            .map(|token| match token {
+                Token::Byte(byte) => Ok(*byte),
+                Token::Param(param) => params
+                    .iter()
+                    .find(|(name, _)| name == param)
+                    .map(|(_, value)| *value)
+                    .ok_or_else(|| format!("{} needs {}=VALUE", self.name, param)),
+            })
+            .collect()
+    }
+}
+
+#[derive(Default)]
+pub struct Config {
+    pub commands: Vec<CustomCommand>,
+    /// Device profiles: names that stand for a device, or a comma-separated list of them.
+    pub devices: Vec<(String, String)>,
+    /// Settings from `[devices.NAME]` profiles, by profile name.
+    pub profiles: Vec<(String, Profile)>,
+    /// Friendly names for inputs, such as `chromecast` for `hdmi3`.
+    pub inputs: Vec<(String, (u8, u8))>,
+    /// Favourite channels: names that stand for a channel number.
+    pub channels: Vec<(String, String)>,
+    /// Extra remote control keys and their IRCC codes, which take precedence over the built-in
+    /// ones.
+    pub keys: Vec<(String, String)>,
+    /// Models from `[models.NAME]` sections and the serial functions they support, which take
+    /// precedence over the built-in ones.
+    pub models: Vec<(String, Vec<u8>)>,
+}
+
+impl Config {
+    pub fn command(&self, name: &str) -> Option<&CustomCommand> {
+        self.commands.iter().find(|c| c.name == name)
+    }
+
+    /// Parses an input given by its label or by its usual name.
+    pub fn parse_input(&self, name: &str) -> Option<(u8, u8)> {
+        match self.inputs.iter().find(|(label, _)| label == name) {
+            Some((_, input)) => Some(*input),
+            None => crate::parse_input(name),
+        }
+    }
+
+    pub fn channel(&self, name: &str) -> Option<&str> {
+        self.channels
+            .iter()
+            .find(|(channel, _)| channel == name)
+            .map(|(_, number)| &number[..])
+    }
+
+    /// Looks up a key's IRCC code, in the config first and then in the built-in table.
+    pub fn key_code(&self, name: &str) -> Option<&str> {
+        match self
+            .keys
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        {
+            Some((_, code)) => Some(code),
+            None => crate::rest::key_code(name),
+        }
+    }
+
+    /// The settings of the profile that names a device, if any.
+    pub fn profile(&self, device: &str) -> Option<Profile> {
+        self.profiles
+            .iter()
+            .find(|(name, _)| self.resolve_devices(name).iter().any(|d| d == device))
+            .map(|(_, profile)| profile.clone())
+    }
+
+    /// Expands profile names in a comma-separated device list.  Anything that isn't a profile
+    /// is taken to be a device already.
+    pub fn resolve_devices(&self, list: &str) -> Vec<String> {
+        let split = |list: &str| -> Vec<String> {
+            list.split(',')
+                .filter(|d| !d.is_empty())
+                .map(String::from)
+                .collect()
+        };
+        split(list)
+            .into_iter()
+            .flat_map(
+                |device| match self.devices.iter().find(|(name, _)| *name == device) {
+                    Some((_, value)) => split(value),
+                    None => vec![device],
+                },
+            )
+            .collect()
+    }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("sony-bravia-cli").join("config.toml"))
+}
+
+/// Writes a starter config file with a single device profile, creating its directory if needed.
+pub fn write_starter(path: &Path, name: &str, device: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let device = device.replace('\\', "\\\\").replace('"', "\\\"");
+    let contents = format!(
+        "\
+# Devices can be given by name instead of by port or URL, e.g. `sony-bravia-cli {name} on`.
+# A name may also stand for a comma-separated list of devices.
+[devices]
+{name} = \"{device}\"
+
+# Inputs can be given friendlier names, used in commands and status output.
+# [inputs]
+# chromecast = \"hdmi3\"
+
+# Custom commands are frame templates in hex; the checksum is added automatically.
+# [commands]
+# picture-mode = \"8c 00 20 03 {{mode}} 00\"
+",
+        name = name,
+        device = device
+    );
+    fs::write(path, contents)
+}
+
+/// Loads the config file at `path`, or from the default location if none is given.  A missing
+/// default file is not an error.
+pub fn load(path: Option<&str>) -> Result<Config, String> {
+    let (path, required) = match path {
+        Some(path) => (PathBuf::from(path), true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(Config::default()),
+        },
+    };
+    match fs::read_to_string(&path) {
+        Ok(contents) => parse(&contents).map_err(|e| format!("{}:{}", path.display(), e)),
+        Err(e) if !required && e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+fn parse(contents: &str) -> Result<Config, String> {
+    let mut config = Config::default();
+    let mut section = String::new();
+    let mut sections = Vec::new();
+    // Where each `[devices.NAME]` profile starts, to point at once the whole file is read.
+    let mut profiles = Vec::new();
+    // Power-on inputs by profile, with their lines, resolved once `[inputs]` has been read.
+    let mut power_on_inputs = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| format!("{}: {}", i + 1, message);
+        if let Some(name) = line.strip_prefix('[') {
+            let name = name.split('#').next().unwrap_or_default().trim();
+            match name.strip_suffix(']') {
+                Some(name) => {
+                    section = name.trim().to_string();
+                    if sections.contains(&section) {
+                        return Err(error(format!("duplicate section [{}]", section)));
+                    }
+                    sections.push(section.clone());
+                    if let Some(profile) = section.strip_prefix("devices.") {
+                        profiles.push((profile.to_string(), i + 1));
+                    }
+                }
+                None => return Err(error(String::from("unterminated section header"))),
+            }
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error(String::from("expected KEY = VALUE")))?;
+        let key = key.trim().trim_matches('"');
+        let value = parse_string(value.trim())
+            .or_else(|| parse_integer(value.trim()))
+            .ok_or_else(|| error(format!("'{}' must be a quoted string or a number", key)))?;
+        if let Some(profile) = section.strip_prefix("devices.") {
+            if key == "power_on_input" {
+                power_on_inputs.push((profile.to_string(), value, i + 1));
+            } else {
+                parse_profile_setting(&mut config, profile, key, value).map_err(error)?;
+            }
+            continue;
+        }
+        if let Some(model) = section.strip_prefix("models.") {
+            if key != "functions" {
+                return Err(error(format!("unknown setting '{}' in [{}]", key, section)));
+            }
+            let functions = value
+                .split(',')
+                .map(str::trim)
+                .filter(|f| !f.is_empty())
+                .map(|f| {
+                    crate::parse_function(f).ok_or_else(|| format!("unknown function '{}'", f))
+                })
+                .collect::<Result<_, _>>()
+                .map_err(error)?;
+            config.models.push((model.to_string(), functions));
+            continue;
+        }
+        match &section[..] {
+            "commands" => {
+                let command = CustomCommand::parse(key, &value).map_err(error)?;
+                config.commands.push(command);
+            }
+            "devices" => {
+                if value.trim().is_empty() {
+                    return Err(error(format!("device '{}' is empty", key)));
+                }
+                config.devices.push((key.to_string(), value));
+            }
+            "channels" => {
+                if crate::channel_keys(&value).is_none() {
+                    return Err(error(format!("invalid channel '{}' for '{}'", value, key)));
+                }
+                config.channels.push((key.to_string(), value));
+            }
+            "keys" => {
+                let base64 = |c: char| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=');
+                if value.is_empty() || !value.chars().all(base64) {
+                    return Err(error(format!(
+                        "invalid IRCC code '{}' for '{}'",
+                        value, key
+                    )));
+                }
+                config.keys.push((key.to_string(), value));
+            }
+            "inputs" => match crate::parse_input(&value) {
+                Some(input) => config.inputs.push((key.to_string(), input)),
+                None => return Err(error(format!("invalid input '{}' for '{}'", value, key))),
+            },
+            _ => return Err(error(format!("unknown setting '{}' in [{}]", key, section))),
+        }
+    }
+    for (profile, value, line) in power_on_inputs {
+        match config.parse_input(&value) {
+            Some(input) => profile_settings(&mut config, &profile).power_on_input = Some(input),
+            None => return Err(format!("{}: invalid input '{}'", line, value)),
+        }
+    }
+    for (profile, line) in profiles {
+        if !config.devices.iter().any(|(name, _)| *name == profile) {
+            return Err(format!("{}: profile '{}' has no device", line, profile));
+        }
+        let limits = config
+            .profiles
+            .iter()
+            .find(|(name, _)| *name == profile)
+            .and_then(|(_, settings)| settings.volume_limits);
+        if limits.is_some_and(|limits| limits.min > limits.max) {
+            return Err(format!(
+                "{}: profile '{}' has min_volume above max_volume",
+                line, profile
+            ));
+        }
+        let model = config
+            .profiles
+            .iter()
+            .find(|(name, _)| *name == profile)
+            .and_then(|(_, settings)| settings.model.as_deref());
+        if let Some(model) = model {
+            if crate::Model::find(model, &config).is_none() {
+                return Err(format!(
+                    "{}: profile '{}' has unknown model '{}'",
+                    line, profile, model
+                ));
+            }
+        }
+    }
+    Ok(config)
+}
+
+/// The settings of a `[devices.NAME]` profile, added if this is the first one.
+fn profile_settings<'a>(config: &'a mut Config, profile: &str) -> &'a mut Profile {
+    let settings = &mut config.profiles;
+    let index = match settings.iter().position(|(name, _)| name == profile) {
+        Some(index) => index,
+        None => {
+            settings.push((profile.to_string(), Profile::default()));
+            settings.len() - 1
+        }
+    };
+    &mut settings[index].1
+}
+
+/// Parses a setting in a `[devices.NAME]` profile section.
+fn parse_profile_setting(
+    config: &mut Config,
+    profile: &str,
+    key: &str,
+    value: String,
+) -> Result<(), String> {
+    if key == "device" {
+        if value.trim().is_empty() {
+            return Err(format!("device '{}' is empty", profile));
+        }
+        config.devices.push((profile.to_string(), value));
+        return Ok(());
+    }
+    let settings = profile_settings(config, profile);
+    match key {
+        "min_volume" | "max_volume" => {
+            let volume: u8 = value
+                .parse()
+                .map_err(|_| format!("invalid {} '{}'", key, value))?;
+            let limits = settings
+                .volume_limits
+                .get_or_insert(VolumeLimits { min: 0, max: 100 });
+            match key {
+                "min_volume" => limits.min = volume,
+                _ => limits.max = volume,
+            }
+        }
+        "model" => settings.model = Some(value),
+        "fallback" => {
+            settings.fallbacks = value
+                .split(',')
+                .map(str::trim)
+                .filter(|d| !d.is_empty())
+                .map(String::from)
+                .collect();
+            if settings.fallbacks.is_empty() {
+                return Err(format!("fallback for '{}' is empty", profile));
+            }
+        }
+        _ => {
+            return Err(format!(
+                "unknown setting '{}' in [devices.{}]",
+                key, profile
+            ))
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(contents: &str) -> String {
+        match parse(contents) {
+            Ok(_) => panic!("{:?} parsed", contents),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn full_config() {
+        let config = parse(
+            "\
+# A comment
+[devices]
+office = \"/dev/ttyUSB0,den\"   # two TVs
+
+[devices.den]
+device = \"http://1234@192.168.1.20\"
+min_volume = 5
+max_volume = \"40\"
+power_on_input = \"tv\"
+fallback = \"/dev/ttyUSB1, ssh://pi/dev/ttyUSB0\"
+model = \"small\"
+
+[inputs]
+tv = \"hdmi2\"
+
+[channels]
+news = \"7.1\"
+
+[keys]
+Netflix = \"AAAAAgAAABoAAAB8Aw==\"
+
+[commands]
+picture-mode = \"8c 00 20 03 {mode} 00\"
+
+[models.small]
+functions = \"power, volume, 0x30\"
+",
+        )
+        .unwrap();
+        assert_eq!(config.resolve_devices("office"), ["/dev/ttyUSB0", "den"]);
+        assert_eq!(
+            config.resolve_devices("den,/dev/ttyUSB2"),
+            ["http://1234@192.168.1.20", "/dev/ttyUSB2"]
+        );
+        let profile = config.profile("http://1234@192.168.1.20").unwrap();
+        let limits = profile.volume_limits.unwrap();
+        assert_eq!((limits.min, limits.max), (5, 40));
+        assert_eq!(profile.power_on_input, crate::parse_input("hdmi2"));
+        assert_eq!(profile.fallbacks, ["/dev/ttyUSB1", "ssh://pi/dev/ttyUSB0"]);
+        assert_eq!(profile.model.as_deref(), Some("small"));
+        assert!(config.profile("/dev/ttyUSB0").is_none());
+        assert_eq!(config.channel("news"), Some("7.1"));
+        assert_eq!(config.key_code("netflix"), Some("AAAAAgAAABoAAAB8Aw=="));
+        assert_eq!(config.models[0].0, "small");
+        assert_eq!(config.models[0].1.len(), 3);
+        assert!(config.command("picture-mode").is_some());
+    }
+
+    #[test]
+    fn volume_limits() {
+        assert_eq!(
+            error("[devices.den]\ndevice = \"/dev/ttyUSB0\"\nmin_volume = 50\nmax_volume = 20\n"),
+            "1: profile 'den' has min_volume above max_volume"
+        );
+        assert_eq!(
+            error("[devices.den]\ndevice = \"/dev/ttyUSB0\"\nmax_volume = 300\n"),
+            "3: invalid max_volume '300'"
+        );
+    }
+
+    #[test]
+    fn unknown_settings() {
+        assert_eq!(
+            error("[inputs]\ntv = \"hdmi2\"\n[input]\ntv = \"hdmi2\"\n"),
+            "4: unknown setting 'tv' in [input]"
+        );
+        assert_eq!(
+            error("[devices.den]\ndevice = \"/dev/ttyUSB0\"\nvolume = 5\n"),
+            "3: unknown setting 'volume' in [devices.den]"
+        );
+        assert_eq!(
+            error("[models.small]\nfunction = \"power\"\n"),
+            "2: unknown setting 'function' in [models.small]"
+        );
+        assert_eq!(error("tv = \"hdmi2\"\n"), "1: unknown setting 'tv' in []");
+    }
+
+    #[test]
+    fn fallbacks() {
+        let config = parse(
+            "[devices.den]\ndevice = \"/dev/ttyUSB0\"\nfallback = \"/dev/ttyUSB1, http://1234@den\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.profile("/dev/ttyUSB0").unwrap().fallbacks,
+            ["/dev/ttyUSB1", "http://1234@den"]
+        );
+        assert_eq!(
+            error("[devices.den]\ndevice = \"a\"\nfallback = \" , \"\n"),
+            "3: fallback for 'den' is empty"
+        );
+    }
+
+    #[test]
+    fn duplicate_sections() {
+        assert_eq!(
+            error("[inputs]\ntv = \"hdmi2\"\n\n[inputs]\ngame = \"hdmi3\"\n"),
+            "4: duplicate section [inputs]"
+        );
+        assert_eq!(
+            error("[devices.den]\ndevice = \"a\"\n[ devices.den ] # again\n"),
+            "3: duplicate section [devices.den]"
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(error("[inputs\n"), "1: unterminated section header");
+        assert_eq!(error("[inputs]\ntv\n"), "2: expected KEY = VALUE");
+        assert_eq!(
+            error("[inputs]\ntv = hdmi2\n"),
+            "2: 'tv' must be a quoted string or a number"
+        );
+        assert_eq!(
+            error("[inputs]\ntv = \"hdmi2\" trailing\n"),
+            "2: 'tv' must be a quoted string or a number"
+        );
+    }
+
+    #[test]
+    fn bad_values() {
+        assert_eq!(
+            error("[inputs]\ntv = \"hdmi9x\"\n"),
+            "2: invalid input 'hdmi9x' for 'tv'"
+        );
+        assert_eq!(
+            error("[channels]\nnews = \"seven\"\n"),
+            "2: invalid channel 'seven' for 'news'"
+        );
+        assert_eq!(
+            error("[keys]\nhome = \"not base64!\"\n"),
+            "2: invalid IRCC code 'not base64!' for 'home'"
+        );
+        assert_eq!(error("[devices]\nden = \"\"\n"), "2: device 'den' is empty");
+        assert_eq!(
+            error("[devices.den]\ndevice = \"a\"\npower_on_input = \"nowhere\"\n"),
+            "3: invalid input 'nowhere'"
+        );
+        assert_eq!(
+            error("[devices.den]\ndevice = \"a\"\nfallback = \",\"\n"),
+            "3: fallback for 'den' is empty"
+        );
+    }
+
+    #[test]
+    fn profiles() {
+        assert_eq!(
+            error("[devices.den]\nmin_volume = 5\n"),
+            "1: profile 'den' has no device"
+        );
+        assert_eq!(
+            error("[devices.den]\ndevice = \"a\"\nmodel = \"zz\"\n"),
+            "1: profile 'den' has unknown model 'zz'"
+        );
+        assert_eq!(
+            error("[models.small]\nfunctions = \"power, teleport\"\n"),
+            "2: unknown function 'teleport'"
+        );
+    }
+
+    #[test]
+    fn custom_commands() {
+        let command = CustomCommand::parse("picture-mode", "8c 00 20 03 {mode} 00").unwrap();
+        assert_eq!(
+            command.render(&[String::from("mode=0x0a")]).unwrap(),
+            [0x8c, 0x00, 0x20, 0x03, 0x0a, 0x00]
+        );
+        assert_eq!(
+            command.render(&[String::from("mode=12")]).unwrap(),
+            [0x8c, 0x00, 0x20, 0x03, 12, 0x00]
+        );
+        assert_eq!(
+            command.render(&[]).unwrap_err(),
+            "picture-mode needs mode=VALUE"
+        );
+        assert_eq!(
+            command.render(&[String::from("mode")]).unwrap_err(),
+            "expected NAME=VALUE, got 'mode'"
+        );
+        assert_eq!(
+            command.render(&[String::from("mode=256")]).unwrap_err(),
+            "invalid byte value '256'"
+        );
+        assert_eq!(
+            CustomCommand::parse("short", "8c 00").err().unwrap(),
+            "command 'short' is shorter than a frame header"
+        );
+        assert_eq!(
+            CustomCommand::parse("bad", "8c 00 zz").err().unwrap(),
+            "invalid byte 'zz' in command 'bad'"
+        );
+        assert_eq!(
+            error("[commands]\nbad = \"8c 00 zz\"\n"),
+            "2: invalid byte 'zz' in command 'bad'"
+        );
+    }
+}
@@ -0,0 +1,267 @@
+//! Just enough JSON parsing to read the TV's REST API responses.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Clone)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn index(&self, i: usize) -> Option<&Value> {
+        match self {
+            Value::Array(items) => items.get(i),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+/// How deeply arrays and objects may nest. The TV's responses need a handful of levels; the
+/// limit keeps a malicious or broken response from overflowing the stack.
+const MAX_DEPTH: usize = 64;
+
+pub fn parse(text: &str) -> Option<Value> {
+    let mut chars = text.chars().peekable();
+    let value = parse_value(&mut chars, MAX_DEPTH)?;
+    skip_whitespace(&mut chars);
+    match chars.next() {
+        None => Some(value),
+        Some(_) => None,
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+}
+
+fn expect(chars: &mut Peekable<Chars>, word: &str) -> Option<()> {
+    for expected in word.chars() {
+        if chars.next()? != expected {
+            return None;
+        }
+    }
+    Some(())
+}
+
+/// Parses a value, allowing arrays and objects to nest `depth` levels deep.
+fn parse_value(chars: &mut Peekable<Chars>, depth: usize) -> Option<Value> {
+    skip_whitespace(chars);
+    let depth = match chars.peek()? {
+        '[' | '{' => depth.checked_sub(1)?,
+        _ => depth,
+    };
+    match *chars.peek()? {
+        'n' => expect(chars, "null").map(|_| Value::Null),
+        't' => expect(chars, "true").map(|_| Value::Bool(true)),
+        'f' => expect(chars, "false").map(|_| Value::Bool(false)),
+        '"' => parse_string(chars).map(Value::String),
+        '[' => {
+            chars.next();
+            let mut items = Vec::new();
+            skip_whitespace(chars);
+            if chars.next_if_eq(&']').is_some() {
+                return Some(Value::Array(items));
+            }
+            loop {
+                items.push(parse_value(chars, depth)?);
+                skip_whitespace(chars);
+                match chars.next()? {
+                    ',' => continue,
+                    ']' => return Some(Value::Array(items)),
+                    _ => return None,
+                }
+            }
+        }
+        '{' => {
+            chars.next();
+            let mut members = Vec::new();
+            skip_whitespace(chars);
+            if chars.next_if_eq(&'}').is_some() {
+                return Some(Value::Object(members));
+            }
+            loop {
+                skip_whitespace(chars);
+                let key = parse_string(chars)?;
+                skip_whitespace(chars);
+                expect(chars, ":")?;
+                members.push((key, parse_value(chars, depth)?));
+                skip_whitespace(chars);
+                match chars.next()? {
+                    ',' => continue,
+                    '}' => return Some(Value::Object(members)),
+                    _ => return None,
+                }
+            }
+        }
+        _ => {
+            let mut number = String::new();
+            while let Some(c) =
+                chars.next_if(|c| matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+            {
+                number.push(c);
+            }
+            number.parse().ok().map(Value::Number)
+        }
+    }
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> Option<String> {
+    expect(chars, "\"")?;
+    let mut value = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                'b' => value.push('\u{8}'),
+                'f' => value.push('\u{c}'),
+                'u' => {
+                    let mut code = parse_hex4(chars)?;
+                    // Characters outside the Basic Multilingual Plane come as a surrogate pair.
+                    if (0xd800..0xdc00).contains(&code) {
+                        let mut ahead = chars.clone();
+                        if ahead.next() == Some('\\') && ahead.next() == Some('u') {
+                            if let Some(low @ 0xdc00..=0xdfff) = parse_hex4(&mut ahead) {
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                                *chars = ahead;
+                            }
+                        }
+                    }
+                    value.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+fn parse_hex4(chars: &mut Peekable<Chars>) -> Option<u32> {
+    let hex: String = (0..4).filter_map(|_| chars.next()).collect();
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(text: &str) -> Option<String> {
+        parse(text)?.as_str().map(String::from)
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            string(r#""a\"b\\c\/d\n\t\r\b\f""#).unwrap(),
+            "a\"b\\c/d\n\t\r\u{8}\u{c}"
+        );
+        assert_eq!(string(r#""\u00e9\u0041""#).unwrap(), "\u{e9}A");
+    }
+
+    #[test]
+    fn surrogate_pairs() {
+        assert_eq!(string(r#""\ud83d\udcfa""#).unwrap(), "\u{1f4fa}");
+        assert_eq!(string(r#""\uD83D\uDCFA!""#).unwrap(), "\u{1f4fa}!");
+        // A lone surrogate can't be represented, but doesn't take what follows with it.
+        assert_eq!(string(r#""\ud83dx""#).unwrap(), "\u{fffd}x");
+        assert_eq!(string(r#""\ud83d\n""#).unwrap(), "\u{fffd}\n");
+        assert_eq!(string(r#""\ud83d\u0041""#).unwrap(), "\u{fffd}A");
+        assert_eq!(string(r#""\udcfa""#).unwrap(), "\u{fffd}");
+    }
+
+    #[test]
+    fn nesting() {
+        let value =
+            parse(r#" {"result": [{"status": "active", "volume": 12, "mute": false}, null]} "#)
+                .unwrap();
+        let first = value.get("result").unwrap().index(0).unwrap();
+        assert_eq!(first.get("status").unwrap().as_str(), Some("active"));
+        assert_eq!(first.get("volume").unwrap().as_f64(), Some(12.0));
+        assert_eq!(first.get("mute").unwrap().as_bool(), Some(false));
+        assert!(matches!(
+            value.get("result").unwrap().index(1),
+            Some(Value::Null)
+        ));
+        assert_eq!(parse("[[], {}]").unwrap().as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse("-1.5e2").unwrap().as_f64(), Some(-150.0));
+        assert_eq!(parse("0").unwrap().as_f64(), Some(0.0));
+    }
+
+    #[test]
+    fn bad_input() {
+        for text in [
+            "",
+            "{",
+            "[1, 2",
+            "[1 2]",
+            r#"{"a" 1}"#,
+            r#"{a: 1}"#,
+            r#""unterminated"#,
+            r#""\u12""#,
+            r#""\uzzzz""#,
+            "tru",
+            "nul",
+            "1 2",
+            "[1,]",
+        ] {
+            assert!(parse(text).is_none(), "{:?} parsed", text);
+        }
+    }
+
+    #[test]
+    fn depth_limit() {
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(parse(&nested(MAX_DEPTH)).is_some());
+        assert!(parse(&nested(MAX_DEPTH + 1)).is_none());
+        assert!(parse(&"{\"a\":".repeat(MAX_DEPTH + 1)).is_none());
+        // Deep enough to overflow the stack if the depth weren't limited.
+        assert!(parse(&nested(1_000_000)).is_none());
+    }
+}
@@ -1,154 +1,3162 @@
+mod adcp;
+mod backend;
+mod config;
+mod json;
+mod man;
+mod notify;
+mod pjlink;
+mod remote;
+mod rest;
+mod serial;
+mod ssh;
+
 use std::env;
-use std::io::Write;
-use std::time::Duration;
+use std::fmt;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Barrier, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use backend::Backend;
+use serial::{
+    BRIGHTNESS_FUNCTION, COLOR_FUNCTION, CONTRAST_FUNCTION, INPUT_SELECT_FUNCTION, MUTING_FUNCTION,
+    PICTURE_FUNCTIONS, POWER_FUNCTION, SHARPNESS_FUNCTION, VOLUME_CONTROL_FUNCTION,
+};
 
-const CONTROL_REQUEST: u8 = 0x8c;
-const QUERY_REQUEST: u8 = 0x83;
-const CATEGORY: u8 = 0x00;
-const POWER_FUNCTION: u8 = 0x00;
-const VOLUME_CONTROL_FUNCTION: u8 = 0x05;
-const MUTING_FUNCTION: u8 = 0x06;
+/// What a particular TV model is known to support, so unsupported commands can be refused before
+/// anything is sent instead of failing with an unexpected response answer.
+struct Model<'a> {
+    name: &'a str,
+    functions: &'a [u8],
+}
 
-const RESPONSE_HEADER: u8 = 0x70;
-const RESPONSE_ANSWER: u8 = 0x00;
+impl<'a> Model<'a> {
+    /// Finds a model in the config's `[models.NAME]` sections, then among the built-in ones.
+    fn find(name: &str, config: &'a config::Config) -> Option<Model<'a>> {
+        let configured = config
+            .models
+            .iter()
+            .find(|(model, _)| model.eq_ignore_ascii_case(name))
+            .map(|(name, functions)| Model { name, functions });
+        configured.or_else(|| {
+            MODELS
+                .iter()
+                .find(|m| m.name.eq_ignore_ascii_case(name))
+                .map(|m| Model {
+                    name: m.name,
+                    functions: m.functions,
+                })
+        })
+    }
 
-fn checksum(command: &Vec<u8>) -> u8 {
-    let s: u8 = command.iter().sum();
-    return s % 255;
+    /// The names of every model, configured and built-in, for messages.
+    fn names(config: &config::Config) -> Vec<&str> {
+        let configured = config.models.iter().map(|(name, _)| &name[..]);
+        configured.chain(MODELS.iter().map(|m| m.name)).collect()
+    }
+
+    fn supports(&self, function: u8) -> bool {
+        self.functions.contains(&function)
+    }
 }
 
-fn power_on(port: &mut Box<dyn serialport::SerialPort>) {
-    let args = vec![CONTROL_REQUEST, CATEGORY, POWER_FUNCTION, 0x02, 0x01];
-    write_command(port, args);
+const MODELS: &[Model<'static>] = &[Model {
+    name: "xbr-55x950g",
+    functions: &[
+        POWER_FUNCTION,
+        INPUT_SELECT_FUNCTION,
+        VOLUME_CONTROL_FUNCTION,
+        MUTING_FUNCTION,
+    ],
+}];
+
+/// Parses a function as listed in a `[models.NAME]` section: a name, or a `0x`-prefixed hex byte
+/// for functions without one, such as those used by custom commands.
+fn parse_function(name: &str) -> Option<u8> {
+    match name {
+        "power" => Some(POWER_FUNCTION),
+        "input" => Some(INPUT_SELECT_FUNCTION),
+        "volume" => Some(VOLUME_CONTROL_FUNCTION),
+        "mute" => Some(MUTING_FUNCTION),
+        _ => match name.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16).ok(),
+            None => PICTURE_FUNCTIONS
+                .iter()
+                .find(|(picture, _)| *picture == name)
+                .map(|(_, function)| *function),
+        },
+    }
 }
 
-fn power_off(port: &mut Box<dyn serialport::SerialPort>) {
-    let args = vec![CONTROL_REQUEST, CATEGORY, POWER_FUNCTION, 0x02, 0x00];
-    write_command(port, args);
+fn function_name(function: u8) -> &'static str {
+    match function {
+        POWER_FUNCTION => "power",
+        INPUT_SELECT_FUNCTION => "input select",
+        VOLUME_CONTROL_FUNCTION => "volume control",
+        MUTING_FUNCTION => "muting",
+        CONTRAST_FUNCTION => "contrast",
+        BRIGHTNESS_FUNCTION => "brightness",
+        COLOR_FUNCTION => "color",
+        SHARPNESS_FUNCTION => "sharpness",
+        _ => "unknown",
+    }
 }
 
-fn volume_up(port: &mut Box<dyn serialport::SerialPort>) {
-    let args = vec![
-        CONTROL_REQUEST,
-        CATEGORY,
-        VOLUME_CONTROL_FUNCTION,
-        0x03,
-        0x00,
-        0x00,
-    ];
-    write_command(port, args);
+const DEFAULT_JOBS: usize = 8;
+const DEFAULT_WARN_LATENCY: Duration = Duration::from_millis(200);
+const DEFAULT_CRIT_LATENCY: Duration = Duration::from_millis(400);
+const DEFAULT_OFF_TIME: Duration = Duration::from_secs(10);
+const DEFAULT_WARM_UP: Duration = Duration::from_secs(5);
+const DEFAULT_KEY_DELAY: Duration = Duration::from_millis(300);
+/// How long a TV gets to report being on after it is turned on, and how often it is asked.
+const POWER_ON_TIMEOUT: Duration = Duration::from_secs(30);
+const POWER_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// The exit status for bad arguments, as in sysexits.h, so that scripts can tell a mistyped
+/// command from a TV that failed it.
+const EXIT_USAGE: i32 = 64;
+
+#[derive(Debug)]
+enum Error {
+    Open(serialport::Error),
+    Connect(std::io::Error),
+    Io(std::io::Error),
+    Terminal(std::io::Error),
+    UnexpectedHeader,
+    UnexpectedAnswer,
+    InvalidChecksum,
+    ShortResponse,
+    Unsupported(&'static str),
+    Pjlink(&'static str),
+    Adcp(&'static str),
+    Rest(String),
+    /// An error code and message returned by a REST API method.
+    Api(u32, String),
+    UnknownApp(String),
+    /// The device never reached the expected state; says what was being waited for.
+    Timeout(&'static str),
+    /// This many `expect` conditions did not hold.
+    Expectations(usize),
+    /// An error from the last of a device's transports, after this many others were tried.
+    Retried(usize, Box<Error>),
 }
 
-fn volume_down(port: &mut Box<dyn serialport::SerialPort>) {
-    let args = vec![
-        CONTROL_REQUEST,
-        CATEGORY,
-        VOLUME_CONTROL_FUNCTION,
-        0x03,
-        0x00,
-        0x01,
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Open(e) => write!(f, "failed to open port: {}", e),
+            Error::Connect(e) => write!(f, "failed to connect: {}", e),
+            Error::Io(e) => write!(f, "failure to communicate with device: {}", e),
+            Error::Terminal(e) => write!(f, "failed to set up the terminal: {}", e),
+            Error::UnexpectedHeader => f.write_str("unexpected response header"),
+            Error::UnexpectedAnswer => f.write_str("unexpected response answer"),
+            Error::InvalidChecksum => f.write_str("invalid response checksum"),
+            Error::ShortResponse => f.write_str("response data too short"),
+            Error::Unsupported(what) => write!(f, "{} is not supported by this device", what),
+            Error::Pjlink(e) => write!(f, "PJLink: {}", e),
+            Error::Adcp(e) => write!(f, "ADCP: {}", e),
+            Error::Rest(e) => write!(f, "REST API: {}", e),
+            Error::Api(code, message) => write!(f, "REST API: {} (error {})", message, code),
+            Error::UnknownApp(name) => write!(f, "no installed app is called '{}'", name),
+            Error::Timeout(what) => write!(f, "timed out waiting for {}", what),
+            Error::Expectations(1) => f.write_str("1 expectation failed"),
+            Error::Expectations(n) => write!(f, "{} expectations failed", n),
+            Error::Retried(_, e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error {
+    /// A stable name for the kind of error, for `--errors json`.
+    fn kind(&self) -> &'static str {
+        match self {
+            Error::Open(_) => "open",
+            Error::Connect(_) => "connect",
+            Error::Io(_) => "io",
+            Error::Terminal(_) => "terminal",
+            Error::UnexpectedHeader => "unexpected-header",
+            Error::UnexpectedAnswer => "unexpected-answer",
+            Error::InvalidChecksum => "invalid-checksum",
+            Error::ShortResponse => "short-response",
+            Error::Unsupported(_) => "unsupported",
+            Error::Pjlink(_) => "pjlink",
+            Error::Adcp(_) => "adcp",
+            Error::Rest(_) => "rest",
+            Error::Api(_, _) => "api",
+            Error::UnknownApp(_) => "unknown-app",
+            Error::Timeout(_) => "timeout",
+            Error::Expectations(_) => "expectations",
+            Error::Retried(_, e) => e.kind(),
+        }
+    }
+
+    /// Notes how many other transports were tried before this error.  Unsupported commands are
+    /// left as they are, since callers look for those to skip what a device can't do.
+    fn retried(retries: usize, error: Error) -> Error {
+        match error {
+            Error::Unsupported(_) => error,
+            _ if retries == 0 => error,
+            _ => Error::Retried(retries, Box::new(error)),
+        }
+    }
+
+    /// How many other transports were tried before this error, for `--errors json`.
+    fn retries(&self) -> usize {
+        match self {
+            Error::Retried(retries, _) => *retries,
+            _ => 0,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Text,
+    Csv,
+    Json,
+    /// JSON Lines: one object per line, for streaming into `jq` or a log shipper.
+    Jsonl,
+    Yaml,
+    Nagios,
+}
+
+impl Format {
+    fn parse(name: &str) -> Option<Format> {
+        match name {
+            "text" => Some(Format::Text),
+            "csv" => Some(Format::Csv),
+            "json" => Some(Format::Json),
+            "jsonl" => Some(Format::Jsonl),
+            "yaml" => Some(Format::Yaml),
+            "nagios" => Some(Format::Nagios),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Setting {
+    Power,
+    Volume,
+    Mute,
+    Input,
+}
+
+impl Setting {
+    fn parse(name: &str) -> Option<Setting> {
+        match name {
+            "power" => Some(Setting::Power),
+            "volume" => Some(Setting::Volume),
+            "mute" => Some(Setting::Mute),
+            "input" => Some(Setting::Input),
+            _ => None,
+        }
+    }
+
+    fn function(self) -> u8 {
+        match self {
+            Setting::Power => POWER_FUNCTION,
+            Setting::Volume => VOLUME_CONTROL_FUNCTION,
+            Setting::Mute => MUTING_FUNCTION,
+            Setting::Input => INPUT_SELECT_FUNCTION,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Setting::Power => "Power",
+            Setting::Volume => "Volume",
+            Setting::Mute => "Mute",
+            Setting::Input => "Input",
+        }
+    }
+
+    /// A value as returned by `query`, as JSON: volume is a number and the rest are strings.
+    /// Double-quoted JSON strings are valid YAML too, and keep `on` and `off` from being read
+    /// as booleans.
+    fn json_value(self, value: &str) -> String {
+        match self {
+            Setting::Volume => value.to_string(),
+            _ => json_string(value),
+        }
+    }
+
+    /// Queries the setting, formatted the same way it is accepted on the command line.
+    fn query(self, backend: &mut dyn Backend) -> Result<String, Error> {
+        match self {
+            Setting::Power => Ok(on_off(backend.is_powered_on()?).to_string()),
+            Setting::Volume => Ok(backend.get_volume()?.to_string()),
+            Setting::Mute => Ok(on_off(backend.is_muted()?).to_string()),
+            Setting::Input => get_input(backend),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// Comparison operators, with those that start with another operator's character first.
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("!=", Comparison::NotEqual),
+    ("<=", Comparison::LessOrEqual),
+    (">=", Comparison::GreaterOrEqual),
+    ("=", Comparison::Equal),
+    ("<", Comparison::Less),
+    (">", Comparison::Greater),
+];
+
+/// A test against the TV's current state such as `power=on`, `input!=hdmi1` or `volume<=30`.
+/// Only volume can be compared with `<`, `<=`, `>` and `>=`.
+struct Condition {
+    setting: Setting,
+    comparison: Comparison,
+    value: String,
+    /// The condition as written, for messages.
+    expression: String,
+}
+
+impl Condition {
+    /// Parses a condition, checking its value so that a typo isn't taken for a state the TV is
+    /// never in.  Input labels from the config are replaced with the input's name, which is
+    /// what the TV reports.
+    fn parse(expression: &str, config: &config::Config) -> Result<Condition, String> {
+        let invalid = || format!("invalid condition '{}'", expression);
+        let (name, value, comparison) = COMPARISONS
+            .iter()
+            .find_map(|(operator, comparison)| {
+                let (name, value) = expression.split_once(operator)?;
+                Some((name, value.trim(), *comparison))
+            })
+            .ok_or_else(invalid)?;
+        let setting = Setting::parse(name.trim()).ok_or_else(invalid)?;
+        let ordered = !matches!(comparison, Comparison::Equal | Comparison::NotEqual);
+        if ordered && setting != Setting::Volume {
+            return Err(format!("{}: only volume can be compared", invalid()));
+        }
+        let value =
+            match setting {
+                Setting::Power | Setting::Mute => parse_on_off(value)
+                    .map(on_off)
+                    .map(str::to_string)
+                    .ok_or_else(|| format!("{}: expected on or off", invalid()))?,
+                Setting::Volume => value
+                    .parse::<u8>()
+                    .map(|volume| volume.to_string())
+                    .map_err(|_| format!("{}: expected a volume level", invalid()))?,
+                Setting::Input => config
+                    .parse_input(value)
+                    .map(|(kind, number)| input_name(kind, number))
+                    .ok_or_else(|| format!("{}: unknown input", invalid()))?,
+            };
+        Ok(Condition {
+            setting,
+            comparison,
+            value,
+            expression: expression.to_string(),
+        })
+    }
+
+    fn holds(&self, actual: &str) -> bool {
+        let ordering = || Some(actual.parse::<u8>().ok()?.cmp(&self.value.parse().ok()?));
+        match self.comparison {
+            Comparison::Equal => actual == self.value,
+            Comparison::NotEqual => actual != self.value,
+            Comparison::Less => ordering().is_some_and(|o| o.is_lt()),
+            Comparison::LessOrEqual => ordering().is_some_and(|o| o.is_le()),
+            Comparison::Greater => ordering().is_some_and(|o| o.is_gt()),
+            Comparison::GreaterOrEqual => ordering().is_some_and(|o| o.is_ge()),
+        }
+    }
+
+    /// Returns a description of the actual state if the condition does not hold, with the
+    /// input's label if it has one.
+    fn check(&self, backend: &mut dyn Backend, options: &Options) -> Result<Option<String>, Error> {
+        let actual = self.setting.query(backend)?;
+        if self.holds(&actual) {
+            Ok(None)
+        } else {
+            Ok(Some(format!(
+                "{} is {}",
+                self.setting.label().to_lowercase(),
+                options.display(self.setting, &actual)
+            )))
+        }
+    }
+}
+
+/// Behaviour switches that apply to every action.
+struct Options {
+    /// Query the current state before a set command and skip the write if it already matches.
+    only_if_different: bool,
+    /// Only run the action if all of these hold.
+    conditions: Vec<Condition>,
+    /// How long to leave a TV that was just turned on before sending it anything else, since
+    /// TVs ignore commands while they boot.
+    warm_up: Duration,
+    /// Input labels from the config file, shown in status output instead of the input's name.
+    input_labels: Vec<(String, (u8, u8))>,
+    /// Profile settings from the config, by device.
+    profiles: Vec<(String, backend::Profile)>,
+    /// Report errors on stderr as JSON objects instead of text.
+    json_errors: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            only_if_different: false,
+            conditions: Vec::new(),
+            warm_up: DEFAULT_WARM_UP,
+            input_labels: Vec::new(),
+            profiles: Vec::new(),
+            json_errors: false,
+        }
+    }
+}
+
+impl Options {
+    /// Reports a failure on stderr.  `device` and `command` are included in JSON output where
+    /// known; `kind` is an `Error::kind` or, for failures before any device is involved, a
+    /// similar name.
+    fn report_error(&self, kind: &str, device: Option<&str>, command: &str, message: &str) {
+        self.report_retried_error(kind, device, command, message, 0);
+    }
+
+    /// Reports a device's failure on stderr, like `report_error`.
+    fn report_device_error(&self, e: &Error, device: &str, command: &str) {
+        self.report_retried_error(e.kind(), Some(device), command, &e.to_string(), e.retries());
+    }
+
+    /// Reports a failure on stderr, with how many of the device's other transports were tried
+    /// before it.
+    fn report_retried_error(
+        &self,
+        kind: &str,
+        device: Option<&str>,
+        command: &str,
+        message: &str,
+        retries: usize,
+    ) {
+        if !self.json_errors {
+            eprintln!("error: {}", message);
+            return;
+        }
+        eprintln!(
+            "{{\"severity\":\"error\",\"kind\":{},\"device\":{},\"command\":{},\"message\":{},\"retries\":{}}}",
+            json_string(kind),
+            device.map_or_else(|| String::from("null"), json_string),
+            json_string(command),
+            json_string(message),
+            retries
+        );
+    }
+
+    /// Opens a device, applying its profile if it has one.
+    fn open(&self, device: &str) -> Result<Box<dyn Backend>, Error> {
+        let profile = self.profiles.iter().find(|(d, _)| d == device);
+        let backend: Box<dyn Backend> = match profile {
+            Some((_, profile)) if !profile.fallbacks.is_empty() => Box::new(
+                backend::Failover::open(device, &profile.fallbacks, self.json_errors)?,
+            ),
+            _ => backend::open(device)?,
+        };
+        match profile {
+            Some((_, profile)) => Ok(Box::new(backend::Profiled::new(backend, profile.clone()))),
+            None => Ok(backend),
+        }
+    }
+
+    /// Swaps an input's name for its label, if it has one.
+    fn display<'a>(&'a self, setting: Setting, value: &'a str) -> &'a str {
+        let label = match setting {
+            Setting::Input => self
+                .input_labels
+                .iter()
+                .find(|(_, (kind, number))| input_name(*kind, *number) == value),
+            _ => None,
+        };
+        label.map_or(value, |(label, _)| label)
+    }
+}
+
+/// Prints a warning or note, about a device if given, on stderr.  It's a JSON object with
+/// `--errors json` so that stderr stays machine-readable.
+fn report_notice(
+    json_errors: bool,
+    severity: &str,
+    kind: &str,
+    device: Option<&str>,
+    message: &str,
+) {
+    if json_errors {
+        eprintln!(
+            "{{\"severity\":{},\"kind\":{},\"device\":{},\"message\":{}}}",
+            json_string(severity),
+            json_string(kind),
+            device.map_or_else(|| String::from("null"), json_string),
+            json_string(message)
+        );
+    } else if let Some(device) = device {
+        eprintln!("{}: {}: {}", severity, device, message);
+    } else {
+        eprintln!("{}: {}", severity, message);
+    }
+}
+
+enum Action {
+    On,
+    Off,
+    Power,
+    PowerCycle(Duration),
+    VolumeUp,
+    VolumeDown,
+    Mute(Option<bool>),
+    MuteFor(Duration),
+    Status(Option<Setting>, Format),
+    Watch(Watch),
+    Volume(u8),
+    /// A volume as a percentage of the device's volume range.
+    VolumePercent(u8),
+    Input(u8, u8),
+    ToggleInput((u8, u8), (u8, u8)),
+    Snapshot,
+    Expect(Vec<Condition>),
+    Capabilities(Format),
+    Restore(Vec<Change>),
+    Diff(Vec<Change>, Format),
+    /// A remote control key, by its IRCC code.
+    Key(String),
+    SendKeys(Vec<String>, Duration),
+    /// The IRCC codes of the keys that tune to a channel.
+    Channel(Vec<String>),
+    /// Keys and their IRCC codes, for the keyboard keys `remote` maps to them.
+    Remote(Vec<(&'static str, String)>),
+    Apps,
+    Launch(String),
+    OpenUrl(String),
+    Type(String),
+    Info,
+    Firmware,
+    PowerSaving(Option<String>),
+    Led(Option<String>),
+    Playing,
+    /// A picture adjustment by serial function, and the value to set it to if any.
+    Picture(u8, Option<u8>),
+    Freeze(Option<bool>),
+    AudioOutput(Option<String>),
+    OutputVolume(String, Option<u8>),
+    Custom(Vec<u8>),
+}
+
+/// What `status --watch` polls and how it reports changes.
+struct Watch {
+    setting: Option<Setting>,
+    interval: Duration,
+    format: Format,
+    /// Settings whose changes also raise a desktop notification.
+    notify: Vec<Setting>,
+    /// Whether failed polls raise a desktop notification too.
+    notify_errors: bool,
+}
+
+impl Watch {
+    fn settings(&self) -> &[Setting] {
+        match &self.setting {
+            Some(setting) => std::slice::from_ref(setting),
+            None => &SNAPSHOT_SETTINGS[..],
+        }
+    }
+}
+
+/// A single setting to apply, as found in a snapshot file.
+enum Change {
+    Power(bool),
+    Volume(u8),
+    Mute(bool),
+    Input(u8, u8),
+}
+
+impl Change {
+    fn parse(setting: Setting, value: &str, config: &config::Config) -> Result<Change, String> {
+        let change = match setting {
+            Setting::Power => parse_on_off(value).map(Change::Power),
+            Setting::Volume => value.parse().ok().map(Change::Volume),
+            Setting::Mute => parse_on_off(value).map(Change::Mute),
+            Setting::Input => config
+                .parse_input(value)
+                .map(|(kind, number)| Change::Input(kind, number)),
+        };
+        change.ok_or_else(|| format!("invalid {} '{}'", setting.label().to_lowercase(), value))
+    }
+
+    /// The value being set, formatted the same way `Setting::query` reports it.
+    fn value(&self) -> String {
+        match *self {
+            Change::Power(on) => on_off(on).to_string(),
+            Change::Volume(volume) => volume.to_string(),
+            Change::Mute(muted) => on_off(muted).to_string(),
+            Change::Input(kind, number) => input_name(kind, number),
+        }
+    }
+
+    fn setting(&self) -> Setting {
+        match self {
+            Change::Power(_) => Setting::Power,
+            Change::Volume(_) => Setting::Volume,
+            Change::Mute(_) => Setting::Mute,
+            Change::Input(_, _) => Setting::Input,
+        }
+    }
+
+    fn apply(
+        &self,
+        backend: &mut dyn Backend,
+        options: &Options,
+        out: &mut dyn Write,
+    ) -> Result<(), Error> {
+        match *self {
+            Change::Power(on) => set_power(backend, on, options, out),
+            Change::Volume(volume) => set_volume(backend, volume, options, out),
+            Change::Mute(muted) => set_muted(backend, muted, options, out),
+            Change::Input(kind, number) => set_input(backend, kind, number, options, out),
+        }
+    }
+}
+
+impl Action {
+    fn parse(name: &str, args: &[String], config: &config::Config) -> Result<Action, String> {
+        let action = match name {
+            "on" => Action::On,
+            "off" => Action::Off,
+            "power" => Action::Power,
+            "power-cycle" => match args {
+                [] => return Ok(Action::PowerCycle(DEFAULT_OFF_TIME)),
+                [flag, time] if flag == "--off-time" => match parse_duration(time) {
+                    Some(time) => return Ok(Action::PowerCycle(time)),
+                    None => return Err(format!("invalid duration '{}'", time)),
+                },
+                _ => return Err(String::from("power-cycle expects [--off-time DURATION]")),
+            },
+            "volume-up" => Action::VolumeUp,
+            "volume-down" => Action::VolumeDown,
+            "mute" => match args {
+                [] => return Ok(Action::Mute(None)),
+                [flag] if flag == "--for" => {
+                    return Err(String::from("--for expects a duration like 30s"))
+                }
+                [flag, duration] if flag == "--for" => match parse_duration(duration) {
+                    Some(duration) => return Ok(Action::MuteFor(duration)),
+                    None => return Err(format!("invalid duration '{}'", duration)),
+                },
+                [state] => match parse_on_off(state) {
+                    Some(muted) => return Ok(Action::Mute(Some(muted))),
+                    None => return Err(format!("expected on or off, got '{}'", state)),
+                },
+                _ => return Err(String::from("unexpected argument(s)")),
+            },
+            "status" => match args {
+                [] => return Ok(Action::Status(None, Format::Text)),
+                [setting] => match Setting::parse(setting) {
+                    Some(setting) => return Ok(Action::Status(Some(setting), Format::Text)),
+                    None => return Err(format!("unknown setting '{}'", setting)),
+                },
+                _ => return Err(String::from("unexpected argument(s)")),
+            },
+            "volume" => match args {
+                [volume] => match volume.parse() {
+                    Ok(volume) => return Ok(Action::Volume(volume)),
+                    Err(_) => return Err(format!("invalid volume '{}'", volume)),
+                },
+                [volume, flag] if flag == "--percent" => match volume.parse() {
+                    Ok(percent @ 0..=100) => return Ok(Action::VolumePercent(percent)),
+                    _ => return Err(format!("invalid percentage '{}'", volume)),
+                },
+                _ => return Err(String::from("volume expects a level")),
+            },
+            "input" => match args {
+                [input] => match config.parse_input(input) {
+                    Some((kind, number)) => return Ok(Action::Input(kind, number)),
+                    None => return Err(format!("invalid input '{}'", input)),
+                },
+                _ => return Err(String::from("input expects an input name")),
+            },
+            "toggle-input" => match args {
+                [first, second] => match (config.parse_input(first), config.parse_input(second)) {
+                    (Some(first), Some(second)) => return Ok(Action::ToggleInput(first, second)),
+                    (None, _) => return Err(format!("invalid input '{}'", first)),
+                    (_, None) => return Err(format!("invalid input '{}'", second)),
+                },
+                _ => return Err(String::from("toggle-input expects two input names")),
+            },
+            "snapshot" => Action::Snapshot,
+            "capabilities" => Action::Capabilities(Format::Text),
+            "expect" => {
+                let mut conditions = Vec::new();
+                for expression in args {
+                    conditions.push(Condition::parse(expression, config)?);
+                }
+                if conditions.is_empty() {
+                    return Err(String::from("expect expects at least one condition"));
+                }
+                return Ok(Action::Expect(conditions));
+            }
+            "info" => Action::Info,
+            "firmware" => Action::Firmware,
+            "playing" => Action::Playing,
+            "restore" => match args {
+                [path] => return read_snapshot(path, config).map(Action::Restore),
+                _ => return Err(String::from("restore expects a snapshot file")),
+            },
+            "diff" => match args {
+                [path] => {
+                    return read_snapshot(path, config)
+                        .map(|changes| Action::Diff(changes, Format::Text))
+                }
+                _ => return Err(String::from("diff expects a snapshot file")),
+            },
+            "key" => match args {
+                [key] => match config.key_code(key) {
+                    Some(code) => return Ok(Action::Key(code.to_string())),
+                    None => return Err(format!("unknown key '{}'", key)),
+                },
+                _ => return Err(String::from("key expects a key name")),
+            },
+            "send-keys" => {
+                let (keys, delay) = match args {
+                    [keys] => (keys, DEFAULT_KEY_DELAY),
+                    [keys, flag, delay] if flag == "--delay" => match parse_duration(delay) {
+                        Some(delay) => (keys, delay),
+                        None => return Err(format!("invalid duration '{}'", delay)),
+                    },
+                    _ => {
+                        return Err(String::from(
+                            "send-keys expects \"KEY KEY...\" [--delay DURATION]",
+                        ))
+                    }
+                };
+                let codes = keys
+                    .split_whitespace()
+                    .map(|key| match config.key_code(key) {
+                        Some(code) => Ok(code.to_string()),
+                        None => Err(format!("unknown key '{}'", key)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if codes.is_empty() {
+                    return Err(String::from("send-keys expects at least one key"));
+                }
+                return Ok(Action::SendKeys(codes, delay));
+            }
+            "teletext" => {
+                let keys = match args {
+                    [mode] => match teletext_keys(mode) {
+                        Some(keys) => keys,
+                        None => {
+                            return Err(format!(
+                                "invalid mode '{}', expected one of on, off, mix",
+                                mode
+                            ))
+                        }
+                    },
+                    _ => return Err(String::from("teletext expects on, off or mix")),
+                };
+                let codes = keys
+                    .into_iter()
+                    .map(|key| match config.key_code(key) {
+                        Some(code) => Ok(code.to_string()),
+                        None => Err(format!("unknown key '{}'", key)),
+                    })
+                    .collect::<Result<_, _>>()?;
+                return Ok(Action::SendKeys(codes, DEFAULT_KEY_DELAY));
+            }
+            "channel" => match args {
+                [channel] => match channel_keys(config.channel(channel).unwrap_or(channel)) {
+                    Some(keys) => {
+                        let codes = keys
+                            .into_iter()
+                            .map(|key| match config.key_code(key) {
+                                Some(code) => Ok(code.to_string()),
+                                None => Err(format!("unknown key '{}'", key)),
+                            })
+                            .collect::<Result<_, _>>()?;
+                        return Ok(Action::Channel(codes));
+                    }
+                    None => return Err(format!("invalid channel '{}'", channel)),
+                },
+                _ => return Err(String::from("channel expects a number or a name")),
+            },
+            "remote" => Action::Remote(remote::key_codes(|key| config.key_code(key))),
+            "app" => match args {
+                [command] if command == "list" => return Ok(Action::Apps),
+                [command, app] if command == "launch" => return Ok(Action::Launch(app.clone())),
+                _ => return Err(String::from("app expects list or launch APP")),
+            },
+            "open-url" => match args {
+                [url] => return Ok(Action::OpenUrl(url.clone())),
+                _ => return Err(String::from("open-url expects a URL")),
+            },
+            "type" => match args {
+                [] => return Err(String::from("type expects some text")),
+                _ => return Ok(Action::Type(args.join(" "))),
+            },
+            "power-saving" => {
+                return parse_mode(args, &rest::POWER_SAVING_MODES).map(Action::PowerSaving)
+            }
+            "led" => return parse_mode(args, &rest::LED_MODES).map(Action::Led),
+            "freeze" => match args {
+                [] => Action::Freeze(None),
+                [state] => match parse_on_off(state) {
+                    Some(frozen) => return Ok(Action::Freeze(Some(frozen))),
+                    None => return Err(format!("expected on or off, got '{}'", state)),
+                },
+                _ => return Err(String::from("unexpected argument(s)")),
+            },
+            "picture" => {
+                let (name, value) = match args {
+                    [name] => (name, None),
+                    [name, value] => (name, Some(value)),
+                    _ => return Err(String::from("picture expects a setting")),
+                };
+                let function = match PICTURE_FUNCTIONS.iter().find(|(n, _)| n == name) {
+                    Some((_, function)) => *function,
+                    None => return Err(format!("unknown picture setting '{}'", name)),
+                };
+                let value = match value {
+                    Some(value) => match value.parse() {
+                        Ok(value) => Some(value),
+                        Err(_) => return Err(format!("invalid {} '{}'", name, value)),
+                    },
+                    None => None,
+                };
+                return Ok(Action::Picture(function, value));
+            }
+            "audio-output" => {
+                return parse_mode(args, &rest::AUDIO_OUTPUTS).map(Action::AudioOutput)
+            }
+            "output-volume" => {
+                let (output, volume) = match args {
+                    [output] => (output, None),
+                    [output, volume] => (output, Some(volume)),
+                    _ => return Err(String::from("output-volume expects an output")),
+                };
+                if !rest::AUDIO_OUTPUTS.iter().any(|(name, _)| name == output) {
+                    return Err(format!("invalid output '{}'", output));
+                }
+                let volume = match volume {
+                    Some(volume) => match volume.parse() {
+                        Ok(volume) => Some(volume),
+                        Err(_) => return Err(format!("invalid volume '{}'", volume)),
+                    },
+                    None => None,
+                };
+                return Ok(Action::OutputVolume(output.clone(), volume));
+            }
+            "headphone-volume" => match args {
+                [] | [_] => {
+                    let args: Vec<String> = std::iter::once(String::from("headphone"))
+                        .chain(args.iter().cloned())
+                        .collect();
+                    return Action::parse("output-volume", &args, config);
+                }
+                _ => return Err(String::from("unexpected argument(s)")),
+            },
+            _ => match config.command(name) {
+                Some(command) => return command.render(args).map(Action::Custom),
+                None => return Err(String::from("invalid action")),
+            },
+        };
+        if !args.is_empty() {
+            return Err(String::from("unexpected argument(s)"));
+        }
+        Ok(action)
+    }
+
+    /// The protocol functions the action sends.
+    fn functions(&self) -> Vec<u8> {
+        match self {
+            Action::On | Action::Off | Action::Power | Action::PowerCycle(_) => {
+                vec![POWER_FUNCTION]
+            }
+            Action::VolumeUp
+            | Action::VolumeDown
+            | Action::Volume(_)
+            | Action::VolumePercent(_) => {
+                vec![VOLUME_CONTROL_FUNCTION]
+            }
+            Action::Mute(_) | Action::MuteFor(_) => vec![MUTING_FUNCTION],
+            Action::Input(_, _) | Action::ToggleInput(_, _) => vec![INPUT_SELECT_FUNCTION],
+            Action::Status(setting, _) => vec![setting.unwrap_or(Setting::Power).function()],
+            Action::Watch(watch) => watch.settings().iter().map(|s| s.function()).collect(),
+            Action::Snapshot => SNAPSHOT_SETTINGS.iter().map(|s| s.function()).collect(),
+            Action::Expect(conditions) => conditions.iter().map(|c| c.setting.function()).collect(),
+            Action::Restore(changes) | Action::Diff(changes, _) => {
+                changes.iter().map(|c| c.setting().function()).collect()
+            }
+            Action::Capabilities(_)
+            | Action::Key(_)
+            | Action::SendKeys(_, _)
+            | Action::Channel(_)
+            | Action::Remote(_)
+            | Action::Apps
+            | Action::Launch(_)
+            | Action::OpenUrl(_)
+            | Action::Type(_)
+            | Action::Info
+            | Action::Firmware
+            | Action::Playing
+            | Action::PowerSaving(_)
+            | Action::Led(_)
+            | Action::Freeze(_)
+            | Action::AudioOutput(_)
+            | Action::OutputVolume(_, _) => Vec::new(),
+            Action::Picture(function, _) => vec![*function],
+            Action::Custom(frame) => vec![frame[2]],
+        }
+    }
+
+    fn run(
+        &self,
+        backend: &mut dyn Backend,
+        options: &Options,
+        out: &mut dyn Write,
+    ) -> Result<(), Error> {
+        match self {
+            Action::On => set_power(backend, true, options, out),
+            Action::Off => set_power(backend, false, options, out),
+            Action::Power => power_toggle(backend, out),
+            Action::PowerCycle(off_time) => power_cycle(backend, *off_time, out),
+            Action::VolumeUp => backend.volume_up(),
+            Action::VolumeDown => backend.volume_down(),
+            Action::Mute(None) => backend.mute_toggle(),
+            Action::Mute(Some(muted)) => set_muted(backend, *muted, options, out),
+            Action::MuteFor(duration) => mute_for(backend, *duration, out),
+            Action::Status(setting, format) => {
+                print_status(backend, *setting, *format, options, out)
+            }
+            Action::Watch(watch) => watch_status(backend, watch, options, out),
+            Action::Volume(volume) => set_volume(backend, *volume, options, out),
+            Action::VolumePercent(percent) => {
+                let (min, max) = backend.volume_range();
+                let volume = min as u32 + ((max - min) as u32 * *percent as u32 + 50) / 100;
+                set_volume(backend, volume as u8, options, out)
+            }
+            Action::Input(kind, number) => set_input(backend, *kind, *number, options, out),
+            Action::ToggleInput(first, second) => {
+                toggle_input(backend, *first, *second, options, out)
+            }
+            Action::Snapshot => print_snapshot(backend, out),
+            Action::Capabilities(format) => print_capabilities(backend, *format, out),
+            Action::Expect(conditions) => check_expectations(backend, conditions, options, out),
+            Action::Diff(changes, format) => print_diff(backend, changes, *format, options, out),
+            Action::Restore(changes) => {
+                for (i, change) in changes.iter().enumerate() {
+                    let more = i + 1 < changes.len();
+                    if let (Change::Power(true), true) = (change, more) {
+                        let was_on = backend.is_powered_on()?;
+                        change.apply(backend, options, out)?;
+                        if !was_on {
+                            warm_up(backend, options.warm_up)?;
+                        }
+                    } else if let Change::Input(0x00, _) = change {
+                        // Only some transports have a way to select the tuner.
+                        match change.apply(backend, options, out) {
+                            Err(Error::Unsupported(_)) => {
+                                writeln!(out, "Input: tv can't be selected here, skipped")?
+                            }
+                            result => result?,
+                        }
+                    } else {
+                        change.apply(backend, options, out)?;
+                    }
+                }
+                Ok(())
+            }
+            Action::Key(code) => backend.send_code(code),
+            Action::SendKeys(keys, delay) => send_keys(backend, keys, *delay),
+            Action::Channel(codes) => codes.iter().try_for_each(|code| backend.send_code(code)),
+            Action::Remote(codes) => remote::run(backend, codes, out),
+            Action::Apps => print_apps(backend, out),
+            Action::Launch(app) => launch_app(backend, app),
+            Action::OpenUrl(url) => backend.open_url(url),
+            Action::Type(text) => backend.type_text(text),
+            Action::PowerSaving(None) => {
+                writeln!(out, "Power saving: {}", backend.power_saving()?)?;
+                Ok(())
+            }
+            Action::PowerSaving(Some(mode)) => set_mode(
+                backend,
+                "Power saving",
+                mode,
+                |b| b.power_saving(),
+                |b, mode| b.set_power_saving(mode),
+                options,
+                out,
+            ),
+            Action::Led(None) => {
+                writeln!(out, "LED: {}", backend.led_mode()?)?;
+                Ok(())
+            }
+            Action::Led(Some(mode)) => set_mode(
+                backend,
+                "LED",
+                mode,
+                |b| b.led_mode(),
+                |b, mode| b.set_led_mode(mode),
+                options,
+                out,
+            ),
+            Action::Freeze(None) => {
+                writeln!(out, "Freeze: {}", on_off(backend.is_frozen()?))?;
+                Ok(())
+            }
+            Action::Freeze(Some(frozen)) => {
+                if options.only_if_different && backend.is_frozen()? == *frozen {
+                    writeln!(out, "Freeze: already {}", on_off(*frozen))?;
+                    return Ok(());
+                }
+                backend.freeze(*frozen)
+            }
+            Action::AudioOutput(None) => {
+                writeln!(out, "Audio output: {}", backend.audio_output()?)?;
+                Ok(())
+            }
+            Action::AudioOutput(Some(output)) => set_mode(
+                backend,
+                "Audio output",
+                output,
+                |b| b.audio_output(),
+                |b, output| b.set_audio_output(output),
+                options,
+                out,
+            ),
+            Action::OutputVolume(output, None) => {
+                writeln!(out, "Volume: {}", backend.output_volume(output)?)?;
+                Ok(())
+            }
+            Action::OutputVolume(output, Some(volume)) => {
+                if options.only_if_different && backend.output_volume(output)? == *volume {
+                    writeln!(out, "Volume: already {}", volume)?;
+                    return Ok(());
+                }
+                backend.set_output_volume(output, *volume)
+            }
+            Action::Info => {
+                for (label, value) in backend.info()? {
+                    writeln!(out, "{}: {}", label, value)?;
+                }
+                Ok(())
+            }
+            Action::Picture(function, None) => {
+                let value = backend.picture(*function)?;
+                writeln!(out, "{}: {}", picture_label(*function), value)?;
+                Ok(())
+            }
+            Action::Picture(function, Some(value)) => {
+                set_picture(backend, *function, *value, options, out)
+            }
+            Action::Firmware => {
+                writeln!(out, "Firmware: {}", backend.firmware()?)?;
+                Ok(())
+            }
+            Action::Playing => {
+                for (label, value) in backend.playing()? {
+                    writeln!(out, "{}: {}", label, value)?;
+                }
+                Ok(())
+            }
+            Action::Custom(frame) => {
+                let data = backend.send_frame(frame)?;
+                if !data.is_empty() {
+                    let hex: Vec<String> = data.iter().map(|b| format!("{:02x}", b)).collect();
+                    writeln!(out, "{}", hex.join(" "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn get_input(backend: &mut dyn Backend) -> Result<String, Error> {
+    let (kind, number) = backend.get_input()?;
+    Ok(input_name(kind, number))
+}
+
+fn input_name(kind: u8, number: u8) -> String {
+    match kind {
+        0x00 => String::from("tv"),
+        0x02 => format!("video{}", number),
+        0x03 => format!("component{}", number),
+        0x04 => format!("hdmi{}", number),
+        0x05 => format!("pc{}", number),
+        _ => format!("{:02x}-{}", kind, number),
+    }
+}
+
+/// The inverse of `input_name`.  The tuner's number isn't reported the same way everywhere, so
+/// `tv` stands for tuner 0 and inputs are compared by name.
+fn parse_input(name: &str) -> Option<(u8, u8)> {
+    if name == "tv" {
+        return Some((0x00, 0));
+    }
+    if let Some((kind, number)) = name.split_once('-') {
+        return Some((u8::from_str_radix(kind, 16).ok()?, number.parse().ok()?));
+    }
+    let split = name.find(|c: char| c.is_ascii_digit())?;
+    let (kind, number) = name.split_at(split);
+    let kind = match kind {
+        "video" => 0x02,
+        "component" => 0x03,
+        "hdmi" => 0x04,
+        "pc" => 0x05,
+        _ => return None,
+    };
+    Some((kind, number.parse().ok()?))
+}
+
+/// Presses each key in turn, pausing between them so menus have time to open.
+fn send_keys(backend: &mut dyn Backend, codes: &[String], delay: Duration) -> Result<(), Error> {
+    for (i, code) in codes.iter().enumerate() {
+        if i > 0 {
+            thread::sleep(delay);
+        }
+        backend.send_code(code)?;
+    }
+    Ok(())
+}
+
+/// The remote control keys that tune to a channel number such as `7` or `7.1`, ending with
+/// enter so the TV doesn't wait to see if more digits follow.
+fn channel_keys(channel: &str) -> Option<Vec<&'static str>> {
+    const DIGITS: [&str; 10] = [
+        "num0", "num1", "num2", "num3", "num4", "num5", "num6", "num7", "num8", "num9",
     ];
-    write_command(port, args);
+    let (major, minor) = match channel.split_once('.') {
+        Some((major, minor)) => (major, Some(minor)),
+        None => (channel, None),
+    };
+    let digits = |number: &str| -> Option<Vec<&'static str>> {
+        if number.is_empty() {
+            return None;
+        }
+        number
+            .chars()
+            .map(|c| c.to_digit(10).map(|d| DIGITS[d as usize]))
+            .collect()
+    };
+    let mut keys = digits(major)?;
+    if let Some(minor) = minor {
+        keys.push("dot");
+        keys.extend(digits(minor)?);
+    }
+    keys.push("enter");
+    Some(keys)
+}
+
+/// The remote control keys that leave teletext showing as `mode`.  Nothing reports whether text
+/// is showing, so exit closes it first and the teletext key then cycles to text and on to mix.
+fn teletext_keys(mode: &str) -> Option<Vec<&'static str>> {
+    let presses = match mode {
+        "off" => 0,
+        "on" => 1,
+        "mix" => 2,
+        _ => return None,
+    };
+    let mut keys = vec!["exit"];
+    keys.extend(std::iter::repeat_n("teletext", presses));
+    Some(keys)
+}
+
+fn set_power(
+    backend: &mut dyn Backend,
+    on: bool,
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    if options.only_if_different && backend.is_powered_on()? == on {
+        writeln!(out, "Power: already {}", on_off(on))?;
+        return Ok(());
+    }
+    if on {
+        backend.power_on()
+    } else {
+        backend.power_off()
+    }
+}
+
+fn set_muted(
+    backend: &mut dyn Backend,
+    muted: bool,
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    if options.only_if_different && backend.is_muted()? == muted {
+        writeln!(out, "Mute: already {}", on_off(muted))?;
+        return Ok(());
+    }
+    backend.mute_set(muted)
+}
+
+/// Mutes for `duration` and then puts muting back the way it was, so a TV that was already muted
+/// stays muted.
+fn mute_for(
+    backend: &mut dyn Backend,
+    duration: Duration,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let was_muted = backend.is_muted()?;
+    if !was_muted {
+        backend.mute_set(true)?;
+    }
+    writeln!(out, "muted for {}s", duration.as_secs_f64())?;
+    out.flush()?;
+    thread::sleep(duration);
+    if !was_muted {
+        backend.mute_set(false)?;
+    }
+    Ok(())
+}
+
+fn set_volume(
+    backend: &mut dyn Backend,
+    volume: u8,
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    if options.only_if_different && backend.get_volume()? == volume {
+        writeln!(out, "Volume: already {}", volume)?;
+        return Ok(());
+    }
+    backend.volume_set(volume)
+}
+
+fn set_input(
+    backend: &mut dyn Backend,
+    kind: u8,
+    number: u8,
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let name = input_name(kind, number);
+    if options.only_if_different && get_input(backend)? == name {
+        writeln!(out, "Input: already {}", name)?;
+        return Ok(());
+    }
+    backend.input_select(kind, number)
+}
+
+/// How a picture function is labelled in output, e.g. `Brightness`.
+fn picture_label(function: u8) -> String {
+    let name = function_name(function);
+    format!("{}{}", name[..1].to_uppercase(), &name[1..])
+}
+
+fn set_picture(
+    backend: &mut dyn Backend,
+    function: u8,
+    value: u8,
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    if options.only_if_different && backend.picture(function)? == value {
+        writeln!(out, "{}: already {}", picture_label(function), value)?;
+        return Ok(());
+    }
+    backend.set_picture(function, value)
+}
+
+/// Sets one of the named modes REST reports, such as the LED's, with `set`.  With
+/// `--only-if-different` the mode is read with `get` first and left alone if it's already `mode`.
+fn set_mode(
+    backend: &mut dyn Backend,
+    label: &str,
+    mode: &str,
+    get: fn(&mut dyn Backend) -> Result<String, Error>,
+    set: fn(&mut dyn Backend, &str) -> Result<(), Error>,
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    if options.only_if_different && get(backend)? == mode {
+        writeln!(out, "{}: already {}", label, mode)?;
+        return Ok(());
+    }
+    set(backend, mode)
+}
+
+/// Switches to `second` if `first` is selected, and to `first` from anything else.
+fn toggle_input(
+    backend: &mut dyn Backend,
+    first: (u8, u8),
+    second: (u8, u8),
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let (kind, number) = if get_input(backend)? == input_name(first.0, first.1) {
+        second
+    } else {
+        first
+    };
+    backend.input_select(kind, number)?;
+    let input = input_name(kind, number);
+    writeln!(out, "Input: {}", options.display(Setting::Input, &input))?;
+    Ok(())
 }
 
-fn mute_toggle(port: &mut Box<dyn serialport::SerialPort>) {
-    let args = vec![CONTROL_REQUEST, CATEGORY, MUTING_FUNCTION, 0x02, 0x00];
-    write_command(port, args);
+/// Parses the optional mode argument of a setting that is queried without one.
+fn parse_mode(args: &[String], modes: &[(&str, &str)]) -> Result<Option<String>, String> {
+    match args {
+        [] => Ok(None),
+        [mode] if modes.iter().any(|(name, _)| name == mode) => Ok(Some(mode.clone())),
+        [mode] => {
+            let names: Vec<&str> = modes.iter().map(|(name, _)| *name).collect();
+            Err(format!(
+                "invalid mode '{}', expected one of {}",
+                mode,
+                names.join(", ")
+            ))
+        }
+        _ => Err(String::from("unexpected argument(s)")),
+    }
+}
+
+fn print_apps(backend: &mut dyn Backend, out: &mut dyn Write) -> Result<(), Error> {
+    let apps = backend.apps()?;
+    let width = apps.iter().map(|app| app.title.len()).max().unwrap_or(0);
+    for app in apps {
+        writeln!(out, "{:<width$}  {}", app.title, app.uri, width = width)?;
+    }
+    Ok(())
 }
 
-fn is_powered_on(port: &mut Box<dyn serialport::SerialPort>) -> bool {
-    let args = vec![QUERY_REQUEST, CATEGORY, POWER_FUNCTION, 0xff, 0xff];
-    let data = write_command(port, args);
-    return data[0] == 1;
+/// Launches an app by title, or by URI if no installed app has that title.
+fn launch_app(backend: &mut dyn Backend, name: &str) -> Result<(), Error> {
+    let apps = backend.apps()?;
+    match apps.iter().find(|app| app.title.eq_ignore_ascii_case(name)) {
+        Some(app) => backend.launch_app(&app.uri),
+        None if name.contains(['.', ':']) => backend.launch_app(name),
+        None => Err(Error::UnknownApp(name.to_string())),
+    }
 }
 
-fn power_toggle(port: &mut Box<dyn serialport::SerialPort>) {
-    if is_powered_on(port) {
-        println!("is on - turning off!");
-        power_off(port);
+fn power_toggle(backend: &mut dyn Backend, out: &mut dyn Write) -> Result<(), Error> {
+    if backend.is_powered_on()? {
+        writeln!(out, "is on - turning off!")?;
+        backend.power_off()
     } else {
-        println!("is off - turning on!");
-        power_on(port);
+        writeln!(out, "is off - turning on!")?;
+        backend.power_on()
+    }
+}
+
+/// Turns the TV off and, after `off_time`, back on, then waits for it to report being on.
+fn power_cycle(
+    backend: &mut dyn Backend,
+    off_time: Duration,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    backend.power_off()?;
+    writeln!(out, "off for {}s", off_time.as_secs_f64())?;
+    out.flush()?;
+    thread::sleep(off_time);
+    backend.power_on()?;
+    wait_until_on(backend)?;
+    writeln!(out, "Power: on")?;
+    Ok(())
+}
+
+/// Waits for a TV that was just turned on to finish booting: until it reports being on, then for
+/// the warm-up time on top.
+fn warm_up(backend: &mut dyn Backend, warm_up: Duration) -> Result<(), Error> {
+    wait_until_on(backend)?;
+    thread::sleep(warm_up);
+    Ok(())
+}
+
+/// Polls until the TV reports being on.  Some TVs don't answer at all while booting, so failed
+/// queries are retried too until the timeout.
+fn wait_until_on(backend: &mut dyn Backend) -> Result<(), Error> {
+    let deadline = Instant::now() + POWER_ON_TIMEOUT;
+    loop {
+        let result = backend.is_powered_on();
+        if let Ok(true) = result {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return match result {
+                Err(e) => Err(e),
+                _ => Err(Error::Timeout("the TV to turn on")),
+            };
+        }
+        thread::sleep(POWER_POLL_INTERVAL);
+    }
+}
+
+fn print_status(
+    backend: &mut dyn Backend,
+    setting: Option<Setting>,
+    format: Format,
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let setting = setting.unwrap_or(Setting::Power);
+    let value = setting.query(backend)?;
+    let value = options.display(setting, &value);
+    let name = setting.label().to_lowercase();
+    match format {
+        Format::Json => writeln!(
+            out,
+            "{{{}:{}}}",
+            json_string(&name),
+            setting.json_value(value)
+        )?,
+        Format::Yaml => writeln!(out, "{}: {}", name, setting.json_value(value))?,
+        _ => writeln!(out, "{}: {}", setting.label(), value)?,
+    }
+    Ok(())
+}
+
+/// Polls the settings every interval and prints each one that changed, with a timestamp, as
+/// text or JSON Lines.  A failed poll is reported once and polling carries on, so the TV can be
+/// unplugged and back.  Notifications are only raised for changes, not the initial state.
+fn watch_status(
+    backend: &mut dyn Backend,
+    watch: &Watch,
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let settings = watch.settings();
+    let mut last: Vec<Option<String>> = vec![None; settings.len()];
+    let mut last_error = None;
+    loop {
+        match poll_settings(backend, settings) {
+            Ok(values) => {
+                last_error = None;
+                for ((setting, value), previous) in settings.iter().zip(values).zip(&mut last) {
+                    if let Some(value) = value.as_ref().filter(|v| Some(*v) != previous.as_ref()) {
+                        let value = options.display(*setting, value);
+                        if let Format::Jsonl = watch.format {
+                            writeln!(
+                                out,
+                                "{{\"time\":{},\"setting\":{},\"value\":{}}}",
+                                json_string(&timestamp()),
+                                json_string(&setting.label().to_lowercase()),
+                                setting.json_value(value)
+                            )?;
+                        } else {
+                            writeln!(out, "{} {}: {}", timestamp(), setting.label(), value)?;
+                        }
+                        if previous.is_some() && watch.notify.contains(setting) {
+                            match setting {
+                                Setting::Power => notify::send(
+                                    &format!("TV powered {}", value),
+                                    options.json_errors,
+                                ),
+                                _ => notify::send(
+                                    &format!("{}: {}", setting.label(), value),
+                                    options.json_errors,
+                                ),
+                            }
+                        }
+                    }
+                    *previous = value;
+                }
+            }
+            Err(e) => {
+                let message = e.to_string();
+                if last_error.as_ref() != Some(&message) {
+                    if let Format::Jsonl = watch.format {
+                        writeln!(
+                            out,
+                            "{{\"time\":{},\"error\":{}}}",
+                            json_string(&timestamp()),
+                            json_string(&message)
+                        )?;
+                    } else {
+                        writeln!(out, "{} error: {}", timestamp(), message)?;
+                    }
+                    if watch.notify_errors {
+                        notify::send(&format!("TV unreachable: {}", message), options.json_errors);
+                    }
+                    last_error = Some(message);
+                }
+            }
+        }
+        out.flush()?;
+        thread::sleep(watch.interval);
+    }
+}
+
+/// Queries each setting, leaving out those the device can't report.  Only power is queried while
+/// the TV is off, since it answers nothing else in standby.
+fn poll_settings(
+    backend: &mut dyn Backend,
+    settings: &[Setting],
+) -> Result<Vec<Option<String>>, Error> {
+    let on = backend.is_powered_on()?;
+    settings
+        .iter()
+        .map(|setting| match setting {
+            Setting::Power => Ok(Some(on_off(on).to_string())),
+            _ if !on => Ok(None),
+            _ => match setting.query(backend) {
+                Ok(value) => Ok(Some(value)),
+                Err(Error::Unsupported(_)) => Ok(None),
+                Err(e) => Err(e),
+            },
+        })
+        .collect()
+}
+
+/// The current time in UTC as `YYYY-MM-DDTHH:MM:SSZ`.
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format_time(secs)
+}
+
+/// Formats seconds since the epoch as `YYYY-MM-DDTHH:MM:SSZ`.
+fn format_time(secs: u64) -> String {
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Civil date from days since the epoch, counting in 400-year eras starting on March 1st.
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Settings captured by `snapshot`, in the order `restore` applies them.  Power comes first
+/// since the TV rejects everything else while in standby.
+const SNAPSHOT_SETTINGS: [Setting; 4] = [
+    Setting::Power,
+    Setting::Input,
+    Setting::Volume,
+    Setting::Mute,
+];
+
+/// Writes the TV's current settings as `key = value` lines that `restore` can read back.  Only
+/// power is recorded while the TV is off, and settings the device can't report are left out.
+fn print_snapshot(backend: &mut dyn Backend, out: &mut dyn Write) -> Result<(), Error> {
+    if !backend.is_powered_on()? {
+        writeln!(out, "power = \"off\"")?;
+        return Ok(());
+    }
+    for setting in SNAPSHOT_SETTINGS {
+        let value = match setting.query(backend) {
+            Ok(value) => value,
+            Err(Error::Unsupported(_)) => continue,
+            Err(e) => return Err(e),
+        };
+        let name = setting.label().to_lowercase();
+        match setting {
+            Setting::Volume => writeln!(out, "{} = {}", name, value)?,
+            _ => writeln!(out, "{} = \"{}\"", name, value)?,
+        }
+    }
+    Ok(())
+}
+
+/// A query that only succeeds if the device supports something.
+type Probe = fn(&mut dyn Backend) -> Result<(), Error>;
+
+/// What `capabilities` probes, by command name, with the name it has in a `[models.NAME]`
+/// section if it's a serial function.
+const PROBES: [(&str, Option<&str>, Probe); 16] = [
+    ("power", Some("power"), |b| b.is_powered_on().map(drop)),
+    ("input", Some("input"), |b| b.get_input().map(drop)),
+    ("volume", Some("volume"), |b| b.get_volume().map(drop)),
+    ("mute", Some("mute"), |b| b.is_muted().map(drop)),
+    ("brightness", Some("brightness"), |b| {
+        b.picture(BRIGHTNESS_FUNCTION).map(drop)
+    }),
+    ("contrast", Some("contrast"), |b| {
+        b.picture(CONTRAST_FUNCTION).map(drop)
+    }),
+    ("color", Some("color"), |b| {
+        b.picture(COLOR_FUNCTION).map(drop)
+    }),
+    ("sharpness", Some("sharpness"), |b| {
+        b.picture(SHARPNESS_FUNCTION).map(drop)
+    }),
+    ("freeze", None, |b| b.is_frozen().map(drop)),
+    ("audio-output", None, |b| b.audio_output().map(drop)),
+    ("headphone-volume", None, |b| {
+        b.output_volume("headphone").map(drop)
+    }),
+    ("power-saving", None, |b| b.power_saving().map(drop)),
+    ("led", None, |b| b.led_mode().map(drop)),
+    ("app", None, |b| b.apps().map(drop)),
+    ("info", None, |b| b.info().map(drop)),
+    ("playing", None, |b| b.playing().map(drop)),
+];
+
+/// Queries each function to find out which ones the device answers, as text or a JSON object.
+/// The text ends with a `[models.NAME]` section listing the serial functions that answered, to
+/// paste into the config.  A TV that is off refuses everything but power, so it has to be on to
+/// be probed.
+fn print_capabilities(
+    backend: &mut dyn Backend,
+    format: Format,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    if !backend.is_powered_on()? {
+        writeln!(out, "the TV is off, turn it on to probe it")?;
+        return Ok(());
+    }
+    let mut supported = Vec::new();
+    for (name, function, probe) in PROBES {
+        match probe(backend) {
+            Ok(()) => supported.push((name, function, true)),
+            Err(Error::Unsupported(_) | Error::UnexpectedAnswer | Error::Api(..)) => {
+                supported.push((name, function, false))
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if let Format::Json = format {
+        let fields: Vec<String> = supported
+            .iter()
+            .map(|(name, _, yes)| format!("{}:{}", json_string(name), yes))
+            .collect();
+        writeln!(out, "{{{}}}", fields.join(","))?;
+        return Ok(());
     }
+    for (name, _, yes) in &supported {
+        let answer = if *yes { "supported" } else { "not supported" };
+        writeln!(out, "{}: {}", name, answer)?;
+    }
+    let functions: Vec<&str> = supported
+        .iter()
+        .filter(|(_, _, yes)| *yes)
+        .filter_map(|(_, function, _)| *function)
+        .collect();
+    let model = backend
+        .info()
+        .ok()
+        .and_then(|info| info.into_iter().find(|(label, _)| *label == "Model"))
+        .map_or_else(|| String::from("NAME"), |(_, model)| model.to_lowercase());
+    writeln!(out)?;
+    writeln!(out, "[models.{}]", model)?;
+    writeln!(out, "functions = \"{}\"", functions.join(", "))?;
+    Ok(())
 }
 
-fn print_status(port: &mut Box<dyn serialport::SerialPort>) {
-    if is_powered_on(port) {
-        println!("Power: on");
+/// Compares the TV's current settings with a snapshot and prints those that differ, as text or a
+/// JSON array, with inputs by their labels.  While the TV is off only power can be compared, and
+/// settings the device can't report are skipped.
+fn print_diff(
+    backend: &mut dyn Backend,
+    changes: &[Change],
+    format: Format,
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let on = backend.is_powered_on()?;
+    let mut differences = Vec::new();
+    for change in changes {
+        let setting = change.setting();
+        let actual = match setting {
+            Setting::Power => on_off(on).to_string(),
+            _ if !on => continue,
+            _ => match setting.query(backend) {
+                Ok(value) => value,
+                Err(Error::Unsupported(_)) => continue,
+                Err(e) => return Err(e),
+            },
+        };
+        let expected = change.value();
+        if actual != expected {
+            differences.push((setting, expected, actual));
+        }
+    }
+    if let Format::Json = format {
+        let objects: Vec<String> = differences
+            .iter()
+            .map(|(setting, expected, actual)| {
+                format!(
+                    "{{\"setting\":{},\"expected\":{},\"actual\":{}}}",
+                    json_string(&setting.label().to_lowercase()),
+                    setting.json_value(options.display(*setting, expected)),
+                    setting.json_value(options.display(*setting, actual))
+                )
+            })
+            .collect();
+        writeln!(out, "[{}]", objects.join(","))?;
+    } else if differences.is_empty() {
+        writeln!(out, "no differences")?;
     } else {
-        println!("Power: off");
+        for (setting, expected, actual) in differences {
+            writeln!(
+                out,
+                "{} is {}, expected {}",
+                setting.label().to_lowercase(),
+                options.display(setting, &actual),
+                options.display(setting, &expected)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn read_snapshot(path: &str, config: &config::Config) -> Result<Vec<Change>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    parse_snapshot(&contents, config).map_err(|e| format!("{}:{}", path, e))
+}
+
+/// Parses a snapshot as `snapshot` prints it, with errors prefixed by their line number.
+fn parse_snapshot(contents: &str, config: &config::Config) -> Result<Vec<Change>, String> {
+    let mut changes = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = line.split_once('=').and_then(|(name, value)| {
+            let setting = Setting::parse(name.trim())?;
+            Some((setting, value.trim().trim_matches('"')))
+        });
+        let (setting, value) = match parsed {
+            Some(parsed) => parsed,
+            None => return Err(format!("{}: expected SETTING = VALUE", i + 1)),
+        };
+        let change =
+            Change::parse(setting, value, config).map_err(|e| format!("{}: {}", i + 1, e))?;
+        changes.push((setting, change));
+    }
+    changes.sort_by_key(|(setting, _)| SNAPSHOT_SETTINGS.iter().position(|s| s == setting));
+    Ok(changes.into_iter().map(|(_, change)| change).collect())
+}
+
+/// Prints the status of an on/off setting and exits 0 if it is on, 1 if it is off, or 2 if it
+/// could not be queried.
+fn exit_with_status(device: &str, setting: Setting, options: &Options) -> ! {
+    let state = options.open(device).and_then(|mut backend| match setting {
+        Setting::Power => backend.is_powered_on(),
+        Setting::Mute => backend.is_muted(),
+        Setting::Volume | Setting::Input => unreachable!("not an on/off setting"),
+    });
+    match state {
+        Ok(on) => {
+            match setting {
+                Setting::Power => println!("Power: {}", on_off(on)),
+                _ => println!("Mute: {}", on_off(on)),
+            }
+            std::process::exit(if on { 0 } else { 1 });
+        }
+        Err(e) => {
+            options.report_device_error(&e, device, "status");
+            std::process::exit(2);
+        }
+    }
+}
+
+const USAGE: &str = "\
+usage: DEVICE[,DEVICE...] [OPTIONS] ACTION [ARGS]
+       config init [DEVICE [NAME]]
+
+devices:
+  a serial port such as /dev/ttyUSB0, http://[PSK@]HOST[:PORT] for a TV's REST
+  API, pjlink://[PASSWORD@]HOST[:PORT] for a PJLink display on the network,
+  adcp://[PASSWORD@]HOST[:PORT] for a Sony projector,
+  ssh://[USER@]HOST[:PORT]/PATH for a serial port on another machine, or a name
+  from the [devices] section of the config
+
+config init writes a starter config naming DEVICE as NAME (default tv).  Without
+a DEVICE, serial ports are probed for a TV.
+
+actions:
+  on | off | power | volume-up | volume-down
+  power-cycle [--off-time DURATION]
+                  turn off, wait (default 10s), turn back on and check it did
+  mute [on|off]   toggle mute, or set it
+  mute --for DURATION
+                  mute, then restore the previous mute state after DURATION
+  volume LEVEL [--percent]
+                  set the volume, or a percentage of the device's volume range
+  input INPUT     select an input, e.g. hdmi1
+  toggle-input INPUT INPUT
+                  switch to whichever of the two inputs isn't selected
+  status [power|volume|mute|input]
+                  with --watch, keep polling and print changes as they happen
+  snapshot        print the current settings in a form restore can read
+  expect CONDITION...
+                  exit non-zero listing any conditions that don't hold, e.g.
+                  power=on input=hdmi1 volume<=30
+  capabilities    query each function to see which ones the TV supports, and
+                  print them as a [models.NAME] section for the config
+  restore FILE    apply settings saved by snapshot
+  diff FILE       show where the current settings differ from a snapshot
+  key KEY         press a remote control key, e.g. home or netflix (REST only)
+  send-keys \"KEY KEY...\" [--delay DURATION]
+                  press a sequence of keys, waiting DURATION (default 300ms)
+                  between them (REST only)
+  channel CHANNEL tune to a channel number such as 7.1, or a name from the
+                  [channels] section of the config (REST only)
+  teletext on|off|mix
+                  show teletext, hide it or mix it over the picture (REST only)
+  remote          use the keyboard as a remote control (REST only)
+  app list        list installed apps (REST only)
+  app launch APP  launch an app by title or URI (REST only)
+  open-url URL    show a web page on a display in pro mode (REST only)
+  type TEXT       fill in the focused text field (REST only)
+  info            show model, serial number, MAC address and firmware (REST only)
+  firmware        show the firmware version (REST only)
+  playing         show the current input, channel or app (REST only)
+  power-saving [off|low|high|picture-off]
+                  show or set the power saving mode (REST only)
+  led [demo|auto-brightness|dark|simple-response|off]
+                  show or set the LED indicator mode (REST only)
+  freeze [on|off]
+                  show or set picture freeze (ADCP only, TVs have no freeze
+                  command)
+  picture brightness|contrast|color|sharpness [VALUE]
+                  show or set a picture adjustment (serial only)
+  audio-output [speaker|headphone|audio-system]
+                  show or select the audio output (REST only)
+  output-volume OUTPUT [LEVEL]
+                  show or set the volume of one audio output (REST only)
+  headphone-volume [LEVEL]
+                  show or set the headphone volume (REST only)
+  COMMAND [NAME=VALUE...]
+                  run a custom command from the [commands] section of the config
+  inventory       report the model, serial number, firmware and state of every
+                  device
+  health-check    time a query and exit 0/1/2 for ok/warning/critical
+
+options:
+  -j, --jobs JOBS         devices to talk to at once (default 8)
+  --stagger DURATION      wait this long between starting each device
+  --sync                  open every device first, then send to all at once
+  --format FORMAT         output format: text, csv, json, jsonl or yaml for
+                          inventory; json, yaml or nagios for status; jsonl for
+                          status --watch; json for diff; nagios for health-check
+  --errors FORMAT         report errors on stderr as text (default) or json
+  --color WHEN            colour tables: auto (default, unless NO_COLOR is set),
+                          always or never
+  --warn DURATION         health-check warning latency (default 200ms)
+  --crit DURATION         health-check critical latency (default 400ms)
+  --config PATH           config file (default ~/.config/sony-bravia-cli/config.toml)
+  --model MODEL           refuse commands the model is not known to support
+  --watch INTERVAL        with status, poll every INTERVAL (e.g. 5s) until interrupted
+  --notify EVENTS         with --watch, also raise desktop notifications for changes
+                          to these settings, e.g. power,error; error means the TV
+                          stopped answering
+  --exit-code             with status power/mute, exit 0 if on, 1 if off, 2 on error
+  --only-if-different     skip set commands when the TV is already in that state
+  --warm-up DURATION      after restore turns a TV on, wait this long before the
+                          other settings (default 5s)
+  --when CONDITION        only act if SETTING=VALUE or SETTING!=VALUE holds, e.g.
+                          power=on, input!=hdmi1 or volume<=30; may be repeated
+
+Invalid arguments, including conditions that can never hold, exit with status
+64.";
+
+/// Writes a starter config file with a device profile.  The device is probed first, but written
+/// out even if it doesn't answer since the TV may just be unplugged.
+fn config_init(args: &[String], path: Option<&str>, json_errors: bool) -> Result<(), String> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => config::default_path()
+            .ok_or_else(|| String::from("no home directory to put the config file in"))?,
+    };
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()));
+    }
+    let (device, name) = match args {
+        [] => (find_tv()?, "tv"),
+        [device] => (device.clone(), "tv"),
+        [device, name] => (device.clone(), &name[..]),
+        _ => return Err(String::from("config init expects [DEVICE [NAME]]")),
+    };
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "invalid name '{}', use letters, digits, - and _",
+            name
+        ));
+    }
+    if !args.is_empty() {
+        if let Err(e) = backend::open(&device).and_then(|mut b| b.is_powered_on()) {
+            let message = format!("did not answer: {}", e);
+            report_notice(json_errors, "warning", e.kind(), Some(&device), &message);
+        }
+    }
+    config::write_starter(&path, name, &device)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    println!("wrote {}, use '{}' as the device", path.display(), name);
+    Ok(())
+}
+
+/// Probes every serial port for a TV, asking which to use if more than one answers.
+fn find_tv() -> Result<String, String> {
+    let ports = serial::available_ports();
+    if ports.is_empty() {
+        return Err(String::from(
+            "no serial ports found, give the device: config init DEVICE",
+        ));
+    }
+    let mut found = Vec::new();
+    for port in ports {
+        match backend::open(&port).and_then(|mut backend| backend.is_powered_on()) {
+            Ok(on) => {
+                println!("{}: TV found, power {}", port, on_off(on));
+                found.push(port);
+            }
+            Err(e) => println!("{}: {}", port, e),
+        }
+    }
+    match found.len() {
+        0 => Err(String::from(
+            "no TV answered on any serial port, give the device: config init DEVICE",
+        )),
+        1 => Ok(found.remove(0)),
+        _ => loop {
+            for (i, port) in found.iter().enumerate() {
+                println!("  {}) {}", i + 1, port);
+            }
+            print!("use which TV? ");
+            std::io::stdout().flush().map_err(|e| e.to_string())?;
+            let mut answer = String::new();
+            if std::io::stdin()
+                .read_line(&mut answer)
+                .map_err(|e| e.to_string())?
+                == 0
+            {
+                return Err(String::from("no TV chosen"));
+            }
+            match answer.trim().parse::<usize>() {
+                Ok(n) if (1..=found.len()).contains(&n) => return Ok(found.remove(n - 1)),
+                _ => println!("enter a number from 1 to {}", found.len()),
+            }
+        },
     }
 }
 
 fn print_usage() {
-    eprintln!("usage: DEVICE [on|off|power|volume-up|volume-down|mute|status]");
+    eprintln!("{}", USAGE);
 }
 
-fn write_command(port: &mut Box<dyn serialport::SerialPort>, contents: Vec<u8>) -> Vec<u8> {
-    let mut vec = contents.clone();
-    let c = checksum(&vec);
-    vec.push(c);
-    port.write_all(&vec).unwrap();
+/// Reports bad arguments and exits.  The usage text is left out with `--errors json`, so that
+/// stderr stays machine-readable.
+fn usage_error(options: &Options, message: &str) -> ! {
+    if options.json_errors {
+        options.report_error("arguments", None, "", message);
+    } else {
+        print_usage();
+        eprintln!("error: {}", message);
+    }
+    std::process::exit(EXIT_USAGE);
+}
+
+/// Parses durations such as `250ms`, `10s`, `5m` or `1h`.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let split = value.find(|c: char| !c.is_ascii_digit())?;
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    match unit {
+        "ms" => Some(Duration::from_millis(number)),
+        "s" => Some(Duration::from_secs(number)),
+        "m" => Some(Duration::from_secs(number.checked_mul(60)?)),
+        "h" => Some(Duration::from_secs(number.checked_mul(60 * 60)?)),
+        _ => None,
+    }
+}
 
-    let mut resp_buf = vec![0; 3];
-    port.read(resp_buf.as_mut_slice())
-        .expect("failure to read response");
+fn run_device(
+    device: &str,
+    action: &Action,
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let mut backend = options.open(device)?;
+    if conditions_hold(backend.as_mut(), options, out)? {
+        action.run(backend.as_mut(), options, out)?;
+    }
+    Ok(())
+}
 
-    if resp_buf[0] != RESPONSE_HEADER {
-        eprintln!("error: unexpected response header");
-        std::process::exit(1);
+/// Checks every condition, listing those that fail.  Unlike `--when`, all of them are checked so
+/// the whole picture is reported at once.
+fn check_expectations(
+    backend: &mut dyn Backend,
+    conditions: &[Condition],
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let mut failed = 0;
+    for condition in conditions {
+        if let Some(actual) = condition.check(backend, options)? {
+            writeln!(out, "failed: {}, expected {}", actual, condition.expression)?;
+            failed += 1;
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(Error::Expectations(failed)),
+    }
+}
+
+/// Checks every `--when` condition, noting the first one that fails in the output.
+fn conditions_hold(
+    backend: &mut dyn Backend,
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<bool, Error> {
+    for condition in &options.conditions {
+        if let Some(actual) = condition.check(backend, options)? {
+            writeln!(out, "skipped: {}", actual)?;
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// How to spread work over several devices.
+struct Bulk {
+    /// Maximum number of devices in flight at once.
+    jobs: usize,
+    /// Delay between starting consecutive devices, to avoid every display drawing inrush
+    /// current at the same moment.
+    stagger: Duration,
+}
+
+impl Bulk {
+    /// When the `i`th device is due to start, or `None` if that's too far off to represent.
+    fn slot(&self, start: Instant, i: usize) -> Option<Instant> {
+        start.checked_add(self.stagger.checked_mul(u32::try_from(i).ok()?)?)
+    }
+}
+
+/// Runs `f` against every device as paced by `bulk`.  Results are returned in the same order as
+/// `devices`.
+fn run_bulk<T, F>(devices: &[&str], bulk: &Bulk, options: &Options, f: F) -> Vec<Result<T, Error>>
+where
+    T: Send,
+    F: Fn(&str) -> Result<T, Error> + Sync,
+{
+    let start = Instant::now();
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(devices.iter().map(|_| None).collect::<Vec<_>>());
+    thread::scope(|s| {
+        for _ in 0..bulk.jobs.min(devices.len()) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= devices.len() {
+                    break;
+                }
+                if !bulk.stagger.is_zero() {
+                    if let Some(slot) = bulk.slot(start, i) {
+                        thread::sleep(slot.saturating_duration_since(Instant::now()));
+                    }
+                    if options.json_errors {
+                        let message = format!("starting {} of {}", i + 1, devices.len());
+                        report_notice(true, "note", "progress", Some(devices[i]), &message);
+                    } else {
+                        eprintln!("[{}/{}] {}", i + 1, devices.len(), devices[i]);
+                    }
+                }
+                let result = f(devices[i]);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("every device is visited by a worker"))
+        .collect()
+}
+
+/// Runs `action` against a device, collapsing any output onto a single line for the summary.
+fn run_device_captured(device: &str, action: &Action, options: &Options) -> Result<String, Error> {
+    let mut out = Vec::new();
+    run_device(device, action, options, &mut out)?;
+    Ok(collapse_output(&out))
+}
+
+fn collapse_output(out: &[u8]) -> String {
+    let out = String::from_utf8_lossy(out);
+    out.lines().collect::<Vec<_>>().join("; ")
+}
+
+/// Runs `action` on every device at once, for video walls where skew between displays is
+/// visible.  Ports are opened and `--when` conditions checked up front, then all threads are
+/// released from a barrier together so only the action's own frames remain to be sent.
+fn run_synced(devices: &[&str], action: &Action, options: &Options) -> Vec<Result<String, Error>> {
+    let barrier = Barrier::new(devices.len());
+    thread::scope(|s| {
+        let handles: Vec<_> = devices
+            .iter()
+            .map(|device| {
+                let barrier = &barrier;
+                s.spawn(move || {
+                    let mut out = Vec::new();
+                    let prepared = options.open(device).and_then(|mut backend| {
+                        let proceed = conditions_hold(backend.as_mut(), options, &mut out)?;
+                        Ok((backend, proceed))
+                    });
+                    barrier.wait();
+                    let (mut backend, proceed) = prepared?;
+                    if proceed {
+                        action.run(backend.as_mut(), options, &mut out)?;
+                    }
+                    Ok(collapse_output(&out))
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("device thread panicked"))
+            .collect()
+    })
+}
+
+/// Whether to colour output: `--color always` or `never` if given, otherwise only when stdout is
+/// a terminal and `NO_COLOR` isn't set.
+fn color_enabled(choice: Option<bool>) -> bool {
+    choice.unwrap_or_else(|| {
+        env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && std::io::stdout().is_terminal()
+    })
+}
+
+const BOLD: &str = "1";
+const RED: &str = "31";
+const GREEN: &str = "32";
+
+/// The ANSI colour of a table cell, picked by its column: good results and powered-on displays
+/// in green, failures in red.
+fn cell_color(header: &str, cell: &str) -> Option<&'static str> {
+    match (header, cell) {
+        ("RESULT", "ok") | ("POWER", "on") => Some(GREEN),
+        ("RESULT", "error") => Some(RED),
+        ("ERROR", cell) if !cell.is_empty() => Some(RED),
+        _ => None,
+    }
+}
+
+fn print_table(headers: &[&str], rows: &[Vec<String>], color: bool) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    // Padding goes outside the escape codes so the columns still line up.
+    let format_row = |cells: &mut dyn Iterator<Item = &str>, is_header: bool| {
+        let line = cells
+            .zip(headers)
+            .zip(&widths)
+            .map(|((cell, header), width)| {
+                let padding = " ".repeat(width.saturating_sub(cell.len()));
+                let code = match (color, is_header) {
+                    (false, _) => None,
+                    (true, true) => Some(BOLD),
+                    (true, false) => cell_color(header, cell),
+                };
+                match code {
+                    Some(code) => format!("\x1b[{}m{}\x1b[0m{}", code, cell, padding),
+                    None => format!("{}{}", cell, padding),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        line.trim_end().to_string()
+    };
+    println!("{}", format_row(&mut headers.iter().copied(), true));
+    for row in rows {
+        println!("{}", format_row(&mut row.iter().map(String::as_str), false));
+    }
+}
+
+fn print_summary(devices: &[&str], results: &[Result<String, Error>], color: bool) {
+    let rows: Vec<Vec<String>> = devices
+        .iter()
+        .zip(results)
+        .map(|(device, result)| match result {
+            Ok(output) => vec![device.to_string(), String::from("ok"), output.clone()],
+            Err(e) => vec![device.to_string(), String::from("error"), e.to_string()],
+        })
+        .collect();
+    print_table(&["DEVICE", "RESULT", "OUTPUT"], &rows, color);
+}
+
+/// Identity and state of a single device, as reported by `inventory`.  The serial protocol has
+/// no model/serial/firmware queries, so those are only filled in over the network; settings the
+/// TV refuses to report (e.g. volume while in standby) are left empty.
+struct InventoryRecord {
+    device: String,
+    power: Option<bool>,
+    volume: Option<u8>,
+    muted: Option<bool>,
+    input: Option<String>,
+    model: Option<String>,
+    serial: Option<String>,
+    firmware: Option<String>,
+    error: Option<String>,
+}
+
+fn query_inventory(device: &str, options: &Options) -> Result<InventoryRecord, Error> {
+    let mut backend = options.open(device)?;
+    let power = backend.is_powered_on()?;
+    let info = backend.info().unwrap_or_default();
+    let detail = |label: &str| {
+        info.iter()
+            .find(|(l, _)| *l == label)
+            .map(|(_, value)| value.clone())
+    };
+    Ok(InventoryRecord {
+        device: device.to_string(),
+        power: Some(power),
+        volume: backend.get_volume().ok(),
+        muted: backend.is_muted().ok(),
+        input: get_input(backend.as_mut())
+            .ok()
+            .map(|input| options.display(Setting::Input, &input).to_string()),
+        model: detail("Model"),
+        serial: detail("Serial"),
+        firmware: backend.firmware().ok(),
+        error: None,
+    })
+}
+
+fn parse_on_off(value: &str) -> Option<bool> {
+    match value {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn print_inventory(records: &[InventoryRecord], format: Format, color: bool) {
+    let cells = |record: &InventoryRecord| {
+        vec![
+            record.device.clone(),
+            record.power.map(on_off).unwrap_or_default().to_string(),
+            record.volume.map(|v| v.to_string()).unwrap_or_default(),
+            record.muted.map(|m| m.to_string()).unwrap_or_default(),
+            record.input.clone().unwrap_or_default(),
+            record.model.clone().unwrap_or_default(),
+            record.serial.clone().unwrap_or_default(),
+            record.firmware.clone().unwrap_or_default(),
+            record.error.clone().unwrap_or_default(),
+        ]
+    };
+    match format {
+        Format::Text | Format::Nagios => {
+            let rows: Vec<Vec<String>> = records.iter().map(cells).collect();
+            print_table(
+                &[
+                    "DEVICE", "POWER", "VOLUME", "MUTED", "INPUT", "MODEL", "SERIAL", "FIRMWARE",
+                    "ERROR",
+                ],
+                &rows,
+                color,
+            );
+        }
+        Format::Yaml => {
+            let null = || String::from("null");
+            for record in records {
+                println!("- device: {}", json_string(&record.device));
+                let fields = [
+                    ("power", record.power.map(|p| json_string(on_off(p)))),
+                    ("volume", record.volume.map(|v| v.to_string())),
+                    ("muted", record.muted.map(|m| m.to_string())),
+                    ("input", record.input.as_deref().map(json_string)),
+                    ("model", record.model.as_deref().map(json_string)),
+                    ("serial", record.serial.as_deref().map(json_string)),
+                    ("firmware", record.firmware.as_deref().map(json_string)),
+                    ("error", record.error.as_deref().map(json_string)),
+                ];
+                for (name, value) in fields {
+                    println!("  {}: {}", name, value.unwrap_or_else(null));
+                }
+            }
+        }
+        Format::Csv => {
+            println!("device,power,volume,muted,input,model,serial,firmware,error");
+            for record in records {
+                let row: Vec<String> = cells(record).iter().map(|c| csv_field(c)).collect();
+                println!("{}", row.join(","));
+            }
+        }
+        Format::Json | Format::Jsonl => {
+            let null = || String::from("null");
+            let objects: Vec<String> = records
+                .iter()
+                .map(|record| {
+                    format!(
+                        "{{\"device\":{},\"power\":{},\"volume\":{},\"muted\":{},\"input\":{},\"model\":{},\"serial\":{},\"firmware\":{},\"error\":{}}}",
+                        json_string(&record.device),
+                        record.power.map(|p| json_string(on_off(p))).unwrap_or_else(null),
+                        record.volume.map(|v| v.to_string()).unwrap_or_else(null),
+                        record.muted.map(|m| m.to_string()).unwrap_or_else(null),
+                        record.input.as_deref().map(json_string).unwrap_or_else(null),
+                        record.model.as_deref().map(json_string).unwrap_or_else(null),
+                        record.serial.as_deref().map(json_string).unwrap_or_else(null),
+                        record.firmware.as_deref().map(json_string).unwrap_or_else(null),
+                        record.error.as_deref().map(json_string).unwrap_or_else(null),
+                    )
+                })
+                .collect();
+            if let Format::Jsonl = format {
+                for object in objects {
+                    println!("{}", object);
+                }
+            } else {
+                println!("[{}]", objects.join(","));
+            }
+        }
     }
-    if resp_buf[1] != RESPONSE_ANSWER {
-        eprintln!("error: unexpected response answer");
+}
+
+fn inventory(devices: &[&str], bulk: &Bulk, format: Format, color: bool, options: &Options) {
+    let records: Vec<InventoryRecord> = run_bulk(devices, bulk, options, |device| {
+        query_inventory(device, options)
+    })
+    .into_iter()
+    .zip(devices)
+    .map(|(result, device)| {
+        result.unwrap_or_else(|e| InventoryRecord {
+            device: device.to_string(),
+            power: None,
+            volume: None,
+            muted: None,
+            input: None,
+            model: None,
+            serial: None,
+            firmware: None,
+            error: Some(e.to_string()),
+        })
+    })
+    .collect();
+    print_inventory(&records, format, color);
+    if records.iter().any(|r| r.error.is_some()) {
         std::process::exit(1);
     }
-    if vec[0] == QUERY_REQUEST {
-        let mut resp_data_buf = vec![0; resp_buf[2] as usize];
-        port.read(resp_data_buf.as_mut_slice())
-            .expect("failure to read response data");
-        let resp_checksum = resp_data_buf.pop().expect("error");
-        resp_buf.extend(resp_data_buf.clone());
-        if resp_checksum != checksum(&resp_buf) {
-            eprintln!("error: invalid response checksum");
-            std::process::exit(1);
+}
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Health {
+    Ok,
+    Warning,
+    Critical,
+}
+
+impl Health {
+    fn label(self) -> &'static str {
+        match self {
+            Health::Ok => "OK",
+            Health::Warning => "WARNING",
+            Health::Critical => "CRITICAL",
         }
-        return resp_data_buf;
+    }
+
+    fn exit_code(self) -> i32 {
+        match self {
+            Health::Ok => 0,
+            Health::Warning => 1,
+            Health::Critical => 2,
+        }
+    }
+}
+
+struct HealthReport {
+    health: Health,
+    summary: String,
+    latency: Option<Duration>,
+    volume: Option<u8>,
+}
+
+impl HealthReport {
+    fn critical(e: Error) -> HealthReport {
+        HealthReport {
+            health: Health::Critical,
+            summary: e.to_string(),
+            latency: None,
+            volume: None,
+        }
+    }
+}
+
+/// Times a power query against the device and grades the response latency against the
+/// thresholds.  Any failure to get a valid answer is critical.
+fn check_health(device: &str, warn: Duration, crit: Duration, options: &Options) -> HealthReport {
+    let mut backend = match options.open(device) {
+        Ok(backend) => backend,
+        Err(e) => return HealthReport::critical(e),
+    };
+    let start = Instant::now();
+    let power = match backend.is_powered_on() {
+        Ok(power) => power,
+        Err(e) => return HealthReport::critical(e),
+    };
+    let latency = start.elapsed();
+    let health = if latency >= crit {
+        Health::Critical
+    } else if latency >= warn {
+        Health::Warning
     } else {
-        let resp_checksum = resp_buf.pop().expect("error");
-        if resp_checksum != checksum(&resp_buf) {
-            eprintln!("error: invalid response checksum");
-            std::process::exit(1);
+        Health::Ok
+    };
+    HealthReport {
+        health,
+        summary: format!(
+            "power {}, responded in {} ms",
+            on_off(power),
+            latency.as_millis()
+        ),
+        latency: Some(latency),
+        volume: backend.get_volume().ok(),
+    }
+}
+
+/// Renders the reports as a single Nagios plugin output line, with latency and volume as
+/// perfdata.  Perfdata labels are prefixed with the device when checking more than one.
+fn nagios_line(
+    devices: &[&str],
+    reports: &[HealthReport],
+    worst: Health,
+    warn: Duration,
+    crit: Duration,
+) -> String {
+    let label = |device: &str, name: &str| {
+        if devices.len() == 1 {
+            name.to_string()
+        } else {
+            format!("'{} {}'", device, name)
+        }
+    };
+    let mut summaries = Vec::new();
+    let mut perfdata = Vec::new();
+    for (device, report) in devices.iter().zip(reports) {
+        if devices.len() == 1 {
+            summaries.push(report.summary.clone());
+        } else {
+            summaries.push(format!("{}: {}", device, report.summary));
+        }
+        if let Some(latency) = report.latency {
+            perfdata.push(format!(
+                "{}={:.3}s;{:.3};{:.3};0",
+                label(device, "latency"),
+                latency.as_secs_f64(),
+                warn.as_secs_f64(),
+                crit.as_secs_f64()
+            ));
+        }
+        if let Some(volume) = report.volume {
+            perfdata.push(format!("{}={};;;0", label(device, "volume"), volume));
+        }
+    }
+    let mut line = format!("BRAVIA {} - {}", worst.label(), summaries.join("; "));
+    if !perfdata.is_empty() {
+        line.push_str(" | ");
+        line.push_str(&perfdata.join(" "));
+    }
+    line
+}
+
+fn health_check(
+    devices: &[&str],
+    bulk: &Bulk,
+    warn: Duration,
+    crit: Duration,
+    format: Format,
+    options: &Options,
+) {
+    let reports: Vec<HealthReport> = run_bulk(devices, bulk, options, |device| {
+        Ok(check_health(device, warn, crit, options))
+    })
+    .into_iter()
+    .map(|r| r.expect("health checks report failures in their summary"))
+    .collect();
+    let worst = reports
+        .iter()
+        .map(|r| r.health)
+        .fold(Health::Ok, |worst, h| if h > worst { h } else { worst });
+    if let Format::Nagios = format {
+        println!("{}", nagios_line(devices, &reports, worst, warn, crit));
+    } else {
+        for (device, report) in devices.iter().zip(&reports) {
+            if devices.len() == 1 {
+                println!("{} - {}", report.health.label(), report.summary);
+            } else {
+                println!("{}: {} - {}", device, report.health.label(), report.summary);
+            }
         }
-        return vec![0; 0];
     }
+    std::process::exit(worst.exit_code());
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    match args.len() {
-        3 => {}
-        _ => {
-            print_usage();
-            eprintln!("error: unexpected argument(s)");
+    let mut bulk = Bulk {
+        jobs: DEFAULT_JOBS,
+        stagger: Duration::ZERO,
+    };
+    let mut format = Format::Text;
+    let mut warn = DEFAULT_WARN_LATENCY;
+    let mut crit = DEFAULT_CRIT_LATENCY;
+    let mut exit_code = false;
+    // --when conditions, parsed once the config's input labels are known.
+    let mut when = Vec::new();
+    let mut watch = None;
+    let mut color = None;
+    let mut notify = Vec::new();
+    let mut notify_errors = false;
+    let mut sync = false;
+    // The --model name, looked up once the config's models are loaded.
+    let mut model = None;
+    let mut config_path = None;
+    let mut options = Options::default();
+    let mut positional = Vec::new();
+    let args: Vec<String> = env::args().skip(1).collect();
+    // Known before parsing, so that mistakes in the arguments before it are reported as JSON too.
+    options.json_errors = args
+        .iter()
+        .rposition(|arg| arg == "--errors")
+        .is_some_and(|i| args.get(i + 1).is_some_and(|format| format == "json"));
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-j" | "--jobs" => {
+                bulk.jobs = match args.next().and_then(|v| v.parse().ok()) {
+                    Some(n) if n > 0 => n,
+                    _ => usage_error(&options, &format!("{} expects a positive number", arg)),
+                }
+            }
+            "--format" => {
+                format = match args.next().as_deref().and_then(Format::parse) {
+                    Some(format) => format,
+                    None => usage_error(
+                        &options,
+                        "--format expects one of text, csv, json, jsonl, yaml, nagios",
+                    ),
+                }
+            }
+            "--errors" => {
+                options.json_errors = match args.next().as_deref() {
+                    Some("text") => false,
+                    Some("json") => true,
+                    _ => usage_error(&options, "--errors expects text or json"),
+                }
+            }
+            "--color" => {
+                color = match args.next().as_deref() {
+                    Some("auto") => None,
+                    Some("always") => Some(true),
+                    Some("never") => Some(false),
+                    _ => usage_error(&options, "--color expects one of auto, always, never"),
+                }
+            }
+            "--warn" | "--crit" => {
+                let threshold = match args.next().as_deref().and_then(parse_duration) {
+                    Some(threshold) => threshold,
+                    None => {
+                        usage_error(&options, &format!("{} expects a duration like 250ms", arg))
+                    }
+                };
+                if arg == "--warn" {
+                    warn = threshold;
+                } else {
+                    crit = threshold;
+                }
+            }
+            "--stagger" => {
+                bulk.stagger = match args.next().as_deref().and_then(parse_duration) {
+                    Some(stagger) => stagger,
+                    None => usage_error(&options, "--stagger expects a duration like 2s"),
+                }
+            }
+            "--sync" => sync = true,
+            "--model" => match args.next() {
+                Some(name) => model = Some(name),
+                None => usage_error(&options, "--model expects a model name"),
+            },
+            "--config" => match args.next() {
+                Some(path) => config_path = Some(path),
+                None => usage_error(&options, "--config expects a path"),
+            },
+            "--watch" => {
+                watch = match args.next().as_deref().and_then(parse_duration) {
+                    Some(interval) if !interval.is_zero() => Some(interval),
+                    _ => usage_error(&options, "--watch expects an interval like 5s"),
+                }
+            }
+            "--notify" => {
+                let events = args.next().unwrap_or_default();
+                for event in events.split(',') {
+                    match Setting::parse(event) {
+                        Some(setting) => notify.push(setting),
+                        None if event == "error" => notify_errors = true,
+                        None => usage_error(
+                            &options,
+                            "--notify expects a list of power, volume, mute, input or error",
+                        ),
+                    }
+                }
+            }
+            "--exit-code" => exit_code = true,
+            "--only-if-different" => options.only_if_different = true,
+            "--warm-up" => {
+                options.warm_up = match args.next().as_deref().and_then(parse_duration) {
+                    Some(warm_up) => warm_up,
+                    None => usage_error(&options, "--warm-up expects a duration like 5s"),
+                }
+            }
+            "--when" => match args.next() {
+                Some(expression) => when.push(expression),
+                None => usage_error(&options, "--when expects SETTING=VALUE or SETTING!=VALUE"),
+            },
+            _ => positional.push(arg),
+        }
+    }
+
+    if positional.first().map(String::as_str) == Some("generate-man") {
+        print!("{}", man::render(USAGE));
+        return;
+    }
+    if positional.first().map(String::as_str) == Some("config") {
+        let result = match positional.get(1).map(String::as_str) {
+            Some("init") => config_init(
+                &positional[2..],
+                config_path.as_deref(),
+                options.json_errors,
+            ),
+            _ => usage_error(&options, "config expects init"),
+        };
+        if let Err(e) = result {
+            options.report_error("config", None, "config init", &e);
             std::process::exit(1);
         }
+        return;
+    }
+    let command = positional.get(1).map_or("", String::as_str);
+    let config = match config::load(config_path.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            options.report_error("config", None, command, &e);
+            std::process::exit(1);
+        }
+    };
+    options.input_labels = config.inputs.clone();
+    for expression in &when {
+        match Condition::parse(expression, &config) {
+            Ok(condition) => options.conditions.push(condition),
+            Err(e) => usage_error(&options, &e),
+        }
+    }
+    let devices = match positional.len() {
+        0 | 1 => Vec::new(),
+        _ => config.resolve_devices(&positional[0]),
+    };
+    options.profiles = devices
+        .iter()
+        .filter_map(|device| Some((device.clone(), config.profile(device)?)))
+        .collect();
+    let devices: Vec<&str> = devices.iter().map(String::as_str).collect();
+    if devices.is_empty() {
+        usage_error(&options, "unexpected argument(s)");
+    }
+    match &positional[1][..] {
+        "inventory" => {
+            if let Format::Nagios = format {
+                usage_error(&options, "inventory does not support --format nagios");
+            }
+            inventory(&devices, &bulk, format, color_enabled(color), &options);
+            return;
+        }
+        "health-check" => health_check(&devices, &bulk, warn, crit, format, &options),
+        "status" if matches!(format, Format::Nagios) => {
+            health_check(&devices, &bulk, warn, crit, format, &options)
+        }
+        _ => {}
+    }
+    let mut action = match Action::parse(&positional[1], &positional[2..], &config) {
+        Ok(action) => action,
+        Err(e) => {
+            options.report_error("arguments", None, command, &e);
+            std::process::exit(EXIT_USAGE);
+        }
+    };
+    if watch.is_none() && (!notify.is_empty() || notify_errors) {
+        usage_error(&options, "--notify only applies to status --watch");
+    }
+    if let Some(interval) = watch {
+        action = match action {
+            Action::Status(setting, _) => match format {
+                Format::Text | Format::Jsonl => Action::Watch(Watch {
+                    setting,
+                    interval,
+                    format,
+                    notify,
+                    notify_errors,
+                }),
+                _ => usage_error(&options, "--watch only supports --format text or jsonl"),
+            },
+            _ => usage_error(&options, "--watch only applies to status"),
+        };
+    }
+    match &mut action {
+        Action::Capabilities(capabilities_format) => {
+            *capabilities_format = match format {
+                Format::Text | Format::Json => format,
+                _ => usage_error(&options, "capabilities only supports --format text or json"),
+            }
+        }
+        Action::Diff(_, diff_format) => {
+            *diff_format = match format {
+                Format::Text | Format::Json => format,
+                _ => usage_error(&options, "diff only supports --format text or json"),
+            }
+        }
+        Action::Status(_, status_format) => {
+            *status_format = match format {
+                Format::Text | Format::Json | Format::Yaml => format,
+                _ => usage_error(
+                    &options,
+                    "status only supports --format text, json, yaml or nagios",
+                ),
+            }
+        }
+        _ => {}
     }
 
-    let mut port = serialport::new(&args[1], 9600)
-        .timeout(Duration::from_millis(500))
-        .open()
-        .expect("Failed to open port.");
-    match &args[2][..] {
-        "on" => power_on(&mut port),
-        "off" => power_off(&mut port),
-        "power" => power_toggle(&mut port),
-        "volume-up" => volume_up(&mut port),
-        "volume-down" => volume_down(&mut port),
-        "mute" => mute_toggle(&mut port),
-        "status" => print_status(&mut port),
-        _ => {
-            eprintln!("error: invalid action");
+    if let Some(name) = &model {
+        if Model::find(name, &config).is_none() {
+            let known = Model::names(&config).join(", ");
+            let message = format!("unknown model '{}', known models: {}", name, known);
+            usage_error(&options, &message);
+        }
+    }
+    // Devices are checked against --model if given, or otherwise the model in their profile.
+    let functions: Vec<u8> = action
+        .functions()
+        .into_iter()
+        .chain(options.conditions.iter().map(|c| c.setting.function()))
+        .collect();
+    for device in &devices {
+        let profile = options.profiles.iter().find(|(d, _)| d == device);
+        let name = model
+            .as_deref()
+            .or_else(|| profile.and_then(|(_, profile)| profile.model.as_deref()));
+        let model = match name.and_then(|name| Model::find(name, &config)) {
+            Some(model) => model,
+            None => continue,
+        };
+        if let Some(function) = functions.iter().find(|f| !model.supports(**f)) {
+            let message = format!(
+                "{} does not support the {} function",
+                model.name,
+                function_name(*function)
+            );
+            options.report_error("unsupported", Some(device), command, &message);
             std::process::exit(1);
         }
+    }
+
+    match (&action, &devices[..]) {
+        (Action::Snapshot, [_, _, ..]) => usage_error(&options, "snapshot needs a single device"),
+        (Action::Remote(_), [_, _, ..]) => usage_error(&options, "remote needs a single device"),
+        (Action::Watch(_), [_, _, ..]) => usage_error(&options, "--watch needs a single device"),
+        _ => {}
+    }
+
+    if exit_code {
+        match (&action, &devices[..]) {
+            (Action::Status(Some(setting @ (Setting::Power | Setting::Mute)), _), [device]) => {
+                exit_with_status(device, *setting, &options)
+            }
+            (Action::Status(Some(Setting::Power | Setting::Mute), _), _) => {
+                usage_error(&options, "--exit-code needs a single device")
+            }
+            _ => usage_error(
+                &options,
+                "--exit-code only applies to status power and status mute",
+            ),
+        }
+    }
+
+    if devices.len() == 1 {
+        if let Err(e) = run_device(devices[0], &action, &options, &mut std::io::stdout()) {
+            options.report_device_error(&e, devices[0], command);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if sync && !bulk.stagger.is_zero() {
+        usage_error(&options, "--sync and --stagger can't be combined");
+    }
+    if bulk.slot(Instant::now(), devices.len() - 1).is_none() {
+        usage_error(&options, "--stagger is too long for this many devices");
+    }
+    let results = if sync {
+        run_synced(&devices, &action, &options)
+    } else {
+        run_bulk(&devices, &bulk, &options, |device| {
+            run_device_captured(device, &action, &options)
+        })
     };
+    print_summary(&devices, &results, color_enabled(color));
+    if options.json_errors {
+        for (device, result) in devices.iter().zip(&results) {
+            if let Err(e) = result {
+                options.report_device_error(e, device, command);
+            }
+        }
+    }
+    if results.iter().any(Result::is_err) {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::tests::MockTv;
+
+    fn condition(expression: &str) -> Result<Condition, String> {
+        let mut config = config::Config::default();
+        config.inputs.push((String::from("chromecast"), (0x04, 3)));
+        Condition::parse(expression, &config)
+    }
+
+    #[test]
+    fn conditions() {
+        let power = condition("power=on").unwrap();
+        assert!(power.setting == Setting::Power && power.comparison == Comparison::Equal);
+        assert!(power.holds("on") && !power.holds("off"));
+
+        let volume = condition(" volume <= 30 ").unwrap();
+        assert!(volume.comparison == Comparison::LessOrEqual);
+        assert!(volume.holds("30") && volume.holds("0") && !volume.holds("31"));
+        assert!(!volume.holds("unknown"));
+        assert!(condition("volume>0").unwrap().holds("1"));
+        assert!(condition("volume>=5").unwrap().holds("5"));
+        assert!(!condition("volume<5").unwrap().holds("5"));
+        assert!(condition("volume!=5").unwrap().holds("6"));
+
+        // Labels are compared as the input they stand for, which is what the TV reports.
+        let input = condition("input!=chromecast").unwrap();
+        assert_eq!(input.value, "hdmi3");
+        assert_eq!(input.expression, "input!=chromecast");
+        assert!(input.holds("hdmi1") && !input.holds("hdmi3"));
+        assert_eq!(condition("input=hdmi1").unwrap().value, "hdmi1");
+        assert_eq!(condition("mute=off").unwrap().value, "off");
+    }
+
+    #[test]
+    fn bad_conditions() {
+        for (expression, message) in [
+            ("power", "invalid condition 'power'"),
+            ("brightness=5", "invalid condition 'brightness=5'"),
+            (
+                "power=yes",
+                "invalid condition 'power=yes': expected on or off",
+            ),
+            ("mute=", "invalid condition 'mute=': expected on or off"),
+            (
+                "volume=loud",
+                "invalid condition 'volume=loud': expected a volume level",
+            ),
+            (
+                "volume<256",
+                "invalid condition 'volume<256': expected a volume level",
+            ),
+            (
+                "input=hdmi",
+                "invalid condition 'input=hdmi': unknown input",
+            ),
+            (
+                "input=netflix",
+                "invalid condition 'input=netflix': unknown input",
+            ),
+            (
+                "power>on",
+                "invalid condition 'power>on': only volume can be compared",
+            ),
+            (
+                "input<=hdmi2",
+                "invalid condition 'input<=hdmi2': only volume can be compared",
+            ),
+        ] {
+            assert_eq!(condition(expression).err().unwrap(), message);
+        }
+    }
+
+    /// Every input the TV can report has to be accepted in a condition, or it can never be
+    /// tested for.
+    #[test]
+    fn reported_inputs_are_accepted() {
+        for kind in 0..=u8::MAX {
+            for number in [0, 1, 9] {
+                let name = input_name(kind, number);
+                let condition = condition(&format!("input={}", name)).unwrap();
+                assert!(condition.holds(&name), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn tuner_conditions() {
+        let tv = condition("input=tv").unwrap();
+        assert_eq!(tv.value, "tv");
+        assert!(tv.holds("tv") && !tv.holds("hdmi1"));
+        assert!(condition("input!=tv").unwrap().holds("hdmi1"));
+    }
+
+    /// Failed expectations and differences name inputs by their labels, as status does.
+    #[test]
+    fn labelled_reports() {
+        let mut config = config::Config::default();
+        config.inputs.push((String::from("chromecast"), (0x04, 3)));
+        let options = Options {
+            input_labels: config.inputs.clone(),
+            ..Options::default()
+        };
+        let mut tv = MockTv::on();
+        tv.input = (0x04, 3);
+        let conditions = [condition("input=hdmi1").unwrap()];
+        let mut out = Vec::new();
+        assert!(matches!(
+            check_expectations(&mut tv, &conditions, &options, &mut out),
+            Err(Error::Expectations(1))
+        ));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "failed: input is chromecast, expected input=hdmi1\n"
+        );
+
+        let changes = parse_snapshot("input = \"hdmi1\"\n", &config).unwrap();
+        let mut diff = Vec::new();
+        print_diff(&mut tv, &changes, Format::Text, &options, &mut diff).unwrap();
+        assert_eq!(
+            String::from_utf8(diff).unwrap(),
+            "input is chromecast, expected hdmi1\n"
+        );
+        let mut diff = Vec::new();
+        print_diff(&mut tv, &changes, Format::Json, &options, &mut diff).unwrap();
+        assert_eq!(
+            String::from_utf8(diff).unwrap(),
+            "[{\"setting\":\"input\",\"expected\":\"hdmi1\",\"actual\":\"chromecast\"}]\n"
+        );
+    }
+
+    /// The tuner is reported as `tv` whatever its number, so it has to survive a round trip
+    /// through a snapshot.
+    #[test]
+    fn tuner_snapshot() {
+        let config = config::Config::default();
+        let options = Options::default();
+        let mut tv = MockTv::on();
+        tv.input = (0x00, 1);
+        let mut snapshot = Vec::new();
+        print_snapshot(&mut tv, &mut snapshot).unwrap();
+        let snapshot = String::from_utf8(snapshot).unwrap();
+        assert!(snapshot.contains("input = \"tv\"\n"), "{}", snapshot);
+        let changes = parse_snapshot(&snapshot, &config).unwrap();
+
+        let mut diff = Vec::new();
+        print_diff(&mut tv, &changes, Format::Text, &options, &mut diff).unwrap();
+        assert_eq!(String::from_utf8(diff).unwrap(), "no differences\n");
+
+        // Where the tuner can be selected, restore does.
+        let mut tv = MockTv::on();
+        tv.tuner = true;
+        Action::Restore(parse_snapshot(&snapshot, &config).unwrap())
+            .run(&mut tv, &options, &mut Vec::new())
+            .unwrap();
+        assert_eq!(tv.input, (0x00, 0));
+        let mut diff = Vec::new();
+        print_diff(&mut tv, &changes, Format::Text, &options, &mut diff).unwrap();
+        assert_eq!(String::from_utf8(diff).unwrap(), "no differences\n");
+
+        // Elsewhere it's skipped, and the other settings are still restored.
+        let mut tv = MockTv::on();
+        tv.volume = 5;
+        let mut out = Vec::new();
+        Action::Restore(changes)
+            .run(&mut tv, &options, &mut out)
+            .unwrap();
+        assert_eq!(tv.input, (0x04, 1));
+        assert_eq!(tv.volume, 20);
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .contains("tv can't be selected here"));
+    }
+
+    /// An app in the foreground leaves out the input but not the other settings.
+    #[test]
+    fn poll_with_app_showing() {
+        let mut tv = MockTv::on();
+        tv.app = true;
+        let values = poll_settings(&mut tv, &SNAPSHOT_SETTINGS).unwrap();
+        assert_eq!(
+            values,
+            [
+                Some(String::from("on")),
+                None,
+                Some(String::from("20")),
+                Some(String::from("off"))
+            ]
+        );
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_duration("10s"), Some(Duration::from_secs(10)));
+        assert_eq!(parse_duration("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("0s"), Some(Duration::ZERO));
+        for value in [
+            "",
+            "10",
+            "s",
+            "1.5s",
+            "-1s",
+            "10 s",
+            "10d",
+            "18446744073709551615h",
+        ] {
+            assert_eq!(parse_duration(value), None, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn stagger_slots() {
+        let start = Instant::now();
+        let bulk = Bulk {
+            jobs: 1,
+            stagger: Duration::from_secs(2),
+        };
+        assert_eq!(bulk.slot(start, 0), Some(start));
+        assert_eq!(bulk.slot(start, 3), Some(start + Duration::from_secs(6)));
+        let bulk = Bulk {
+            jobs: 1,
+            stagger: Duration::from_secs(u64::MAX / 2),
+        };
+        assert_eq!(bulk.slot(start, 1), None);
+        assert_eq!(bulk.slot(start, 3), None);
+    }
+
+    #[test]
+    fn teletext() {
+        assert_eq!(teletext_keys("off").unwrap(), ["exit"]);
+        assert_eq!(teletext_keys("on").unwrap(), ["exit", "teletext"]);
+        assert_eq!(
+            teletext_keys("mix").unwrap(),
+            ["exit", "teletext", "teletext"]
+        );
+        assert_eq!(teletext_keys("page"), None);
+    }
+
+    #[test]
+    fn channels() {
+        assert_eq!(channel_keys("7").unwrap(), ["num7", "enter"]);
+        assert_eq!(
+            channel_keys("105").unwrap(),
+            ["num1", "num0", "num5", "enter"]
+        );
+        assert_eq!(
+            channel_keys("7.12").unwrap(),
+            ["num7", "dot", "num1", "num2", "enter"]
+        );
+        for channel in ["", ".1", "7.", "7.1.2", "seven", "-7", "7a"] {
+            assert_eq!(channel_keys(channel), None, "{:?}", channel);
+        }
+    }
+
+    #[test]
+    fn inputs() {
+        for name in ["tv", "video1", "component2", "hdmi4", "pc1", "0a-3"] {
+            match parse_input(name) {
+                Some((kind, number)) => assert_eq!(input_name(kind, number), name),
+                None => assert_eq!(name, "tv"),
+            }
+        }
+        assert_eq!(parse_input("hdmi"), None);
+        assert_eq!(parse_input("dvd1"), None);
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(parse_function("power"), Some(POWER_FUNCTION));
+        assert_eq!(parse_function("contrast"), Some(CONTRAST_FUNCTION));
+        assert_eq!(parse_function("0x30"), Some(0x30));
+        assert_eq!(parse_function("0x300"), None);
+        assert_eq!(parse_function("teleport"), None);
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(format_time(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_time(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_time(1_709_251_199), "2024-02-29T23:59:59Z");
+        assert_eq!(format_time(4_107_542_400), "2100-03-01T00:00:00Z");
+    }
 }
@@ -0,0 +1,64 @@
+//! Renders the usage text as a roff man page, for `generate-man`.  Working from the usage text
+//! rather than a separate page keeps the two from drifting apart.
+//!
+//! The usage text is laid out as `usage:` lines, then `name:` headed sections of two-space
+//! indented entries with the term and its description separated by at least two spaces.
+//! Description lines that don't fit are continued further indented.  Anything else is prose.
+
+pub fn render(usage: &str) -> String {
+    let mut page = format!(
+        ".TH SONY-BRAVIA-CLI 1 \"\" \"sony-bravia-cli {}\" \"User Commands\"\n\
+         .SH NAME\n\
+         sony-bravia-cli \\- control Sony Bravia TVs and displays\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    let mut section = "";
+    let mut paragraph = false;
+    for line in usage.lines() {
+        let indent = line.len() - line.trim_start().len();
+        let text = line.trim();
+        if let Some(synopsis) = text.strip_prefix("usage:") {
+            section = "SYNOPSIS";
+            page.push_str(".SH SYNOPSIS\n");
+            page.push_str(&format!(
+                ".B sony-bravia-cli\n{}\n",
+                escape(synopsis.trim())
+            ));
+        } else if text.is_empty() {
+            section = "";
+            paragraph = true;
+        } else if section == "SYNOPSIS" {
+            page.push_str(&format!(".br\n.B sony-bravia-cli\n{}\n", escape(text)));
+        } else if indent == 0 && text.ends_with(':') && !text.contains(' ') {
+            section = text.trim_end_matches(':');
+            page.push_str(&format!(".SH {}\n", section.to_uppercase()));
+        } else if indent == 2 && section != "devices" {
+            match text.split_once("  ") {
+                Some((term, description)) => page.push_str(&format!(
+                    ".TP\n.B {}\n{}\n",
+                    escape(term),
+                    escape(description.trim())
+                )),
+                None => page.push_str(&format!(".TP\n.B {}\n", escape(text))),
+            }
+        } else {
+            if paragraph {
+                page.push_str(".PP\n");
+            }
+            page.push_str(&format!("{}\n", escape(text)));
+        }
+        paragraph = paragraph && text.is_empty();
+    }
+    page
+}
+
+/// Escapes text for roff: backslashes, hyphens (which would otherwise render as typographic
+/// dashes and break copy-pasted options) and control characters at the start of a line.
+fn escape(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    if escaped.starts_with(['.', '\'']) {
+        format!("\\&{}", escaped)
+    } else {
+        escaped
+    }
+}
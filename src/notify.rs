@@ -0,0 +1,122 @@
+//! Desktop notifications, raised through the platform's own command line tool so no
+//! notification library is needed: `notify-send` on Linux and the BSDs, `osascript` on macOS
+//! and a PowerShell balloon tip on Windows.
+
+use std::io;
+use std::process::{Command, Stdio};
+use std::thread;
+
+const TITLE: &str = "sony-bravia-cli";
+
+/// Raises a notification in the background, so a slow notifier doesn't hold up polling.
+/// Failing to is only worth a warning, since the same event has already been printed.
+pub fn send(message: &str, json_errors: bool) {
+    let mut command = command(message);
+    thread::spawn(move || {
+        let result = command
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .and_then(|status| {
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(io::Error::other(status.to_string()))
+                }
+            });
+        if let Err(e) = result {
+            let message = format!("failed to show notification: {}", e);
+            crate::report_notice(json_errors, "warning", "notification", None, &message);
+        }
+    });
+}
+
+#[cfg(target_os = "macos")]
+fn command(message: &str) -> Command {
+    let script = format!(
+        "display notification {} with title {}",
+        applescript_string(message),
+        applescript_string(TITLE)
+    );
+    let mut command = Command::new("osascript");
+    command.args(["-e", &script]);
+    command
+}
+
+#[cfg(target_os = "macos")]
+fn applescript_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The message is passed through the environment so it needs no PowerShell quoting.
+#[cfg(windows)]
+fn command(message: &str) -> Command {
+    const SCRIPT: &str = "\
+        Add-Type -AssemblyName System.Windows.Forms; \
+        $n = New-Object System.Windows.Forms.NotifyIcon; \
+        $n.Icon = [System.Drawing.SystemIcons]::Information; \
+        $n.Visible = $true; \
+        $n.ShowBalloonTip(5000, $env:BRAVIA_TITLE, $env:BRAVIA_MESSAGE, 'Info'); \
+        Start-Sleep -Seconds 5; \
+        $n.Dispose()";
+    let mut command = Command::new("powershell");
+    command
+        .args(["-NoProfile", "-Command", SCRIPT])
+        .env("BRAVIA_TITLE", TITLE)
+        .env("BRAVIA_MESSAGE", message);
+    command
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn command(message: &str) -> Command {
+    let mut command = Command::new("notify-send");
+    command.args(["--", TITLE, message]);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(windows))]
+    fn args(command: &Command) -> Vec<String> {
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn notification_command() {
+        let command = command(r#"den: "quoted" \ path"#);
+        assert_eq!(command.get_program(), "osascript");
+        assert_eq!(
+            args(&command),
+            [
+                "-e",
+                r#"display notification "den: \"quoted\" \\ path" with title "sony-bravia-cli""#
+            ]
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn notification_command() {
+        let command = command("den: 'quoted' $env:PATH");
+        assert_eq!(command.get_program(), "powershell");
+        let message = command
+            .get_envs()
+            .find(|(name, _)| *name == "BRAVIA_MESSAGE")
+            .and_then(|(_, value)| value);
+        assert_eq!(message.unwrap(), "den: 'quoted' $env:PATH");
+    }
+
+    #[cfg(not(any(windows, target_os = "macos")))]
+    #[test]
+    fn notification_command() {
+        let command = command("-den: power is on");
+        assert_eq!(command.get_program(), "notify-send");
+        assert_eq!(args(&command), ["--", TITLE, "-den: power is on"]);
+    }
+}
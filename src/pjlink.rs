@@ -0,0 +1,239 @@
+//! PJLink class 1, the network control protocol shared by projectors and professional displays
+//! from many vendors.  Class 1 covers power, input and audio/video mute but not volume.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::backend::{self, Backend};
+use crate::Error;
+
+const DEFAULT_PORT: u16 = 4352;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// PJLink input types alongside the serial protocol's input kinds they stand in for.  Storage
+/// and network inputs have no serial equivalent.
+const INPUT_TYPES: [(u8, u8); 3] = [(b'1', 0x05), (b'2', 0x02), (b'3', 0x04)];
+
+pub struct Pjlink {
+    stream: BufReader<TcpStream>,
+    /// The authentication digest, which is only sent along with the first command.
+    digest: Option<String>,
+}
+
+impl Pjlink {
+    /// Connects to `[PASSWORD@]HOST[:PORT]` and handles the greeting.
+    pub fn connect(address: &str) -> Result<Pjlink, Error> {
+        let (password, host) = backend::split_password(address);
+        let stream = backend::connect(host, DEFAULT_PORT, TIMEOUT)?;
+        let mut pjlink = Pjlink {
+            stream: BufReader::new(stream),
+            digest: None,
+        };
+        let greeting = pjlink.read_line()?;
+        match greeting.strip_prefix("PJLINK ") {
+            Some("0") => {}
+            Some(seed) if seed.starts_with("1 ") => {
+                let password = password.ok_or(Error::Pjlink("password required"))?;
+                let digest = md5(format!("{}{}", &seed[2..], password).as_bytes());
+                let hex: Vec<String> = digest.iter().map(|b| format!("{:02x}", b)).collect();
+                pjlink.digest = Some(hex.concat());
+            }
+            Some("ERRA") => return Err(Error::Pjlink("authentication failed")),
+            _ => return Err(Error::UnexpectedHeader),
+        }
+        Ok(pjlink)
+    }
+
+    fn read_line(&mut self) -> Result<String, Error> {
+        let mut line = Vec::new();
+        self.stream.read_until(b'\r', &mut line)?;
+        if line.pop() != Some(b'\r') {
+            return Err(Error::ShortResponse);
+        }
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    /// Sends `%1COMMAND PARAMETER` and returns whatever follows the `=` in the response.
+    fn command(&mut self, command: &str, parameter: &str) -> Result<String, Error> {
+        let digest = self.digest.take().unwrap_or_default();
+        let request = format!("{}%1{} {}\r", digest, command, parameter);
+        self.stream.get_mut().write_all(request.as_bytes())?;
+        let line = self.read_line()?;
+        if line == "PJLINK ERRA" {
+            return Err(Error::Pjlink("authentication failed"));
+        }
+        let response = line
+            .strip_prefix("%1")
+            .and_then(|rest| rest.strip_prefix(command))
+            .and_then(|rest| rest.strip_prefix('='))
+            .ok_or(Error::UnexpectedHeader)?;
+        match response {
+            "ERR1" => Err(Error::Pjlink("undefined command")),
+            "ERR2" => Err(Error::Pjlink("out of parameter")),
+            "ERR3" => Err(Error::Pjlink("unavailable time")),
+            "ERR4" => Err(Error::Pjlink("display failure")),
+            _ => Ok(response.to_string()),
+        }
+    }
+
+    fn set(&mut self, command: &str, parameter: &str) -> Result<(), Error> {
+        match &self.command(command, parameter)?[..] {
+            "OK" => Ok(()),
+            _ => Err(Error::UnexpectedAnswer),
+        }
+    }
+}
+
+impl Backend for Pjlink {
+    fn power_on(&mut self) -> Result<(), Error> {
+        self.set("POWR", "1")
+    }
+
+    fn power_off(&mut self) -> Result<(), Error> {
+        self.set("POWR", "0")
+    }
+
+    /// Warming up counts as on and cooling down as off.
+    fn is_powered_on(&mut self) -> Result<bool, Error> {
+        match &self.command("POWR", "?")?[..] {
+            "1" | "3" => Ok(true),
+            "0" | "2" => Ok(false),
+            _ => Err(Error::UnexpectedAnswer),
+        }
+    }
+
+    fn mute_set(&mut self, muted: bool) -> Result<(), Error> {
+        self.set("AVMT", if muted { "21" } else { "20" })
+    }
+
+    /// Only audio mute is reported; video mute on its own leaves the sound on.
+    fn is_muted(&mut self) -> Result<bool, Error> {
+        match &self.command("AVMT", "?")?[..] {
+            "21" | "31" => Ok(true),
+            "10" | "11" | "20" | "30" => Ok(false),
+            _ => Err(Error::UnexpectedAnswer),
+        }
+    }
+
+    fn input_select(&mut self, kind: u8, number: u8) -> Result<(), Error> {
+        let input_type = INPUT_TYPES
+            .iter()
+            .find(|(_, k)| *k == kind)
+            .map(|(t, _)| *t as char)
+            .ok_or(Error::Unsupported("this input type"))?;
+        if !(1..=9).contains(&number) {
+            return Err(Error::Unsupported("this input number"));
+        }
+        self.set("INPT", &format!("{}{}", input_type, number))
+    }
+
+    fn get_input(&mut self) -> Result<(u8, u8), Error> {
+        let response = self.command("INPT", "?")?;
+        match response.as_bytes() {
+            [input_type, number @ b'1'..=b'9'] => INPUT_TYPES
+                .iter()
+                .find(|(t, _)| t == input_type)
+                .map(|(_, kind)| (*kind, number - b'0'))
+                .ok_or(Error::Unsupported("this input type")),
+            _ => Err(Error::UnexpectedAnswer),
+        }
+    }
+}
+
+/// MD5, as PJLink uses it to hash the password with the projector's random seed.
+fn md5(message: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 64] = [
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5,
+        9, 14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10,
+        15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+    ];
+    const K: [u32; 64] = [
+        0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613,
+        0xfd469501, 0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193,
+        0xa679438e, 0x49b40821, 0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d,
+        0x02441453, 0xd8a1e681, 0xe7d3fbc8, 0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed,
+        0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a, 0xfffa3942, 0x8771f681, 0x6d9d6122,
+        0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70, 0x289b7ec6, 0xeaa127fa,
+        0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665, 0xf4292244,
+        0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+        0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb,
+        0xeb86d391,
+    ];
+
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((message.len() as u64) * 8).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for chunk in padded.chunks(64) {
+        let words: Vec<u32> = chunk
+            .chunks(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(K[i])
+                .wrapping_add(words[g])
+                .rotate_left(SHIFTS[i]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0; 16];
+    for (out, s) in digest.chunks_mut(4).zip(state) {
+        out.copy_from_slice(&s.to_le_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The test suite from RFC 1321, appendix A.5.
+    #[test]
+    fn md5_vectors() {
+        for (message, digest) in [
+            ("", "d41d8cd98f00b204e9800998ecf8427e"),
+            ("a", "0cc175b9c0f1b6a831c399e269772661"),
+            ("abc", "900150983cd24fb0d6963f7d28e17f72"),
+            ("message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+            (
+                "abcdefghijklmnopqrstuvwxyz",
+                "c3fcd3d76192e4007dfb496cca67e13b",
+            ),
+            (
+                "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+                "d174ab98d277d9f5a5611c2c9f419d9f",
+            ),
+            (
+                "12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+                "57edf4a22be3c955ac49da2e2107b67a",
+            ),
+        ] {
+            let hex: String = md5(message.as_bytes())
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            assert_eq!(hex, digest, "MD5 of {:?}", message);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! `remote`: turns the keyboard into a remote control, pressing a key on the TV for each key
+//! typed.  Raw mode is set up with `stty`, so this needs a Unix terminal.
+
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+
+use crate::backend::Backend;
+use crate::Error;
+
+/// Keyboard input and the remote control keys it presses.  The arrow keys are the escape
+/// sequences xterm-compatible terminals send.
+const KEYMAP: &[(&[u8], &str)] = &[
+    (b"\x1b[A", "up"),
+    (b"\x1b[B", "down"),
+    (b"\x1b[C", "right"),
+    (b"\x1b[D", "left"),
+    (b"\r", "confirm"),
+    (b"\x7f", "return"),
+    (b"\x08", "return"),
+    (b"h", "home"),
+    (b"o", "options"),
+    (b"i", "input"),
+    (b"p", "power"),
+    (b"+", "volume-up"),
+    (b"=", "volume-up"),
+    (b"-", "volume-down"),
+    (b"m", "mute"),
+    (b"]", "channel-up"),
+    (b"[", "channel-down"),
+    (b"0", "num0"),
+    (b"1", "num1"),
+    (b"2", "num2"),
+    (b"3", "num3"),
+    (b"4", "num4"),
+    (b"5", "num5"),
+    (b"6", "num6"),
+    (b"7", "num7"),
+    (b"8", "num8"),
+    (b"9", "num9"),
+];
+
+const HELP: &str = "\
+arrows, enter, backspace: navigate    h: home    o: options    i: input
++/-: volume    m: mute    [/]: channel    0-9: digits    p: power    q: quit";
+
+/// The terminal in raw mode, restored to its previous settings when dropped.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        let saved = stty(&["-g"])?.trim().to_string();
+        stty(&["raw", "-echo"])?;
+        Ok(RawMode { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+/// Runs `stty` on the terminal connected to stdin and returns its output.
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("stty failed, is stdin a terminal?"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The keys the keymap presses and their IRCC codes, as found by `key_code`.  Keys without a
+/// code are left out.
+pub fn key_codes<'a>(key_code: impl Fn(&str) -> Option<&'a str>) -> Vec<(&'static str, String)> {
+    KEYMAP
+        .iter()
+        .filter_map(|(_, key)| Some((*key, key_code(key)?.to_string())))
+        .collect()
+}
+
+/// Reads key presses until `q`, Ctrl-C or Ctrl-D, and sends each key's code from `codes`.
+/// Failed key presses are reported and the session carries on, unless the device can't take
+/// keys at all.
+pub fn run(
+    backend: &mut dyn Backend,
+    codes: &[(&str, String)],
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    writeln!(out, "{}", HELP)?;
+    let _raw = RawMode::enable().map_err(Error::Terminal)?;
+    let mut stdin = io::stdin().lock();
+    let mut buf = [0; 8];
+    loop {
+        let n = stdin.read(&mut buf)?;
+        let input = &buf[..n];
+        if matches!(input, b"" | b"q" | b"\x03" | b"\x04") {
+            return Ok(());
+        }
+        let key = match KEYMAP.iter().find(|(bytes, _)| *bytes == input) {
+            Some((_, key)) => key,
+            None => continue,
+        };
+        // Raw mode turns off output processing too, so lines need an explicit carriage return.
+        write!(out, "{}\r\n", key)?;
+        out.flush()?;
+        let code = match codes.iter().find(|(name, _)| name == key) {
+            Some((_, code)) => code,
+            None => {
+                write!(out, "error: no code for {}\r\n", key)?;
+                continue;
+            }
+        };
+        match backend.send_code(code) {
+            Ok(()) => {}
+            Err(e @ Error::Unsupported(_)) => return Err(e),
+            Err(e) => write!(out, "error: {}\r\n", e)?,
+        }
+    }
+}
@@ -0,0 +1,687 @@
+//! The REST API of IP-connected TVs: JSON-RPC services under `/sony/` plus the IRCC-IP SOAP
+//! endpoint for remote control keys.  Requests are authenticated with the pre-shared key set
+//! under the TV's IP control settings.
+
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use crate::backend::{self, App, Backend};
+use crate::{json, json_string, Error};
+
+const DEFAULT_PORT: u16 = 80;
+/// The error `getPlayingContentInfo` returns while an app is in the foreground.
+const ILLEGAL_STATE: u32 = 7;
+/// What an app in the foreground can't report.
+const APP_INPUT: &str = "the input while an app is showing";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Remote control keys and their IRCC codes, as listed by the TV's `getRemoteControllerInfo`.
+const KEYS: &[(&str, &str)] = &[
+    ("num1", "AAAAAQAAAAEAAAAAAw=="),
+    ("num2", "AAAAAQAAAAEAAAABAw=="),
+    ("num3", "AAAAAQAAAAEAAAACAw=="),
+    ("num4", "AAAAAQAAAAEAAAADAw=="),
+    ("num5", "AAAAAQAAAAEAAAAEAw=="),
+    ("num6", "AAAAAQAAAAEAAAAFAw=="),
+    ("num7", "AAAAAQAAAAEAAAAGAw=="),
+    ("num8", "AAAAAQAAAAEAAAAHAw=="),
+    ("num9", "AAAAAQAAAAEAAAAIAw=="),
+    ("num0", "AAAAAQAAAAEAAAAJAw=="),
+    ("dot", "AAAAAgAAAJcAAAAdAw=="),
+    ("enter", "AAAAAQAAAAEAAAALAw=="),
+    ("power", "AAAAAQAAAAEAAAAVAw=="),
+    ("power-off", "AAAAAQAAAAEAAAAvAw=="),
+    ("wake-up", "AAAAAQAAAAEAAAAuAw=="),
+    ("input", "AAAAAQAAAAEAAAAlAw=="),
+    ("tv", "AAAAAQAAAAEAAAAkAw=="),
+    ("volume-up", "AAAAAQAAAAEAAAASAw=="),
+    ("volume-down", "AAAAAQAAAAEAAAATAw=="),
+    ("mute", "AAAAAQAAAAEAAAAUAw=="),
+    ("channel-up", "AAAAAQAAAAEAAAAQAw=="),
+    ("channel-down", "AAAAAQAAAAEAAAARAw=="),
+    ("jump", "AAAAAQAAAAEAAAA7Aw=="),
+    ("display", "AAAAAQAAAAEAAAA6Aw=="),
+    ("teletext", "AAAAAQAAAAEAAAA/Aw=="),
+    ("home", "AAAAAQAAAAEAAABgAw=="),
+    ("exit", "AAAAAQAAAAEAAABjAw=="),
+    ("confirm", "AAAAAQAAAAEAAABlAw=="),
+    ("up", "AAAAAQAAAAEAAAB0Aw=="),
+    ("down", "AAAAAQAAAAEAAAB1Aw=="),
+    ("left", "AAAAAQAAAAEAAAA0Aw=="),
+    ("right", "AAAAAQAAAAEAAAAzAw=="),
+    ("return", "AAAAAgAAAJcAAAAjAw=="),
+    ("options", "AAAAAgAAAJcAAAA2Aw=="),
+    ("subtitle", "AAAAAgAAAJcAAAAoAw=="),
+    ("audio", "AAAAAgAAAJcAAAAXAw=="),
+    ("red", "AAAAAgAAAJcAAAAlAw=="),
+    ("green", "AAAAAgAAAJcAAAAmAw=="),
+    ("yellow", "AAAAAgAAAJcAAAAnAw=="),
+    ("blue", "AAAAAgAAAJcAAAAkAw=="),
+    ("play", "AAAAAgAAAJcAAAAaAw=="),
+    ("pause", "AAAAAgAAAJcAAAAZAw=="),
+    ("stop", "AAAAAgAAAJcAAAAYAw=="),
+    ("next", "AAAAAgAAAJcAAAA9Aw=="),
+    ("prev", "AAAAAgAAAJcAAAA8Aw=="),
+    ("forward", "AAAAAgAAAJcAAAAcAw=="),
+    ("rewind", "AAAAAgAAAJcAAAAbAw=="),
+    ("guide", "AAAAAgAAAKQAAABbAw=="),
+    ("action-menu", "AAAAAgAAAMQAAABLAw=="),
+    ("netflix", "AAAAAgAAABoAAAB8Aw=="),
+    ("youtube", "AAAAAgAAAMQAAABHAw=="),
+    ("hdmi1", "AAAAAgAAABoAAABaAw=="),
+    ("hdmi2", "AAAAAgAAABoAAABbAw=="),
+    ("hdmi3", "AAAAAgAAABoAAABcAw=="),
+    ("hdmi4", "AAAAAgAAABoAAABdAw=="),
+];
+
+pub fn key_code(name: &str) -> Option<&'static str> {
+    KEYS.iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, code)| *code)
+}
+
+/// Power saving modes as named on the command line and by the API.
+pub const POWER_SAVING_MODES: [(&str, &str); 4] = [
+    ("off", "off"),
+    ("low", "low"),
+    ("high", "high"),
+    ("picture-off", "pictureOff"),
+];
+
+/// LED indicator modes as named on the command line and by the API.
+pub const LED_MODES: [(&str, &str); 5] = [
+    ("demo", "Demo"),
+    ("auto-brightness", "AutoBrightnessAdjust"),
+    ("dark", "Dark"),
+    ("simple-response", "SimpleResponse"),
+    ("off", "Off"),
+];
+
+fn api_name(modes: &[(&'static str, &'static str)], name: &str) -> Result<&'static str, Error> {
+    let mode = modes.iter().find(|(n, _)| *n == name);
+    mode.map(|(_, api)| *api)
+        .ok_or(Error::Unsupported("this mode"))
+}
+
+/// The command line name for a value reported by the API.  Values without one, such as the
+/// `speaker_hdmi` output some sets report, are shown as the API has them.
+fn cli_name(modes: &[(&'static str, &'static str)], api: &str) -> String {
+    let mode = modes.iter().find(|(_, a)| *a == api);
+    mode.map_or(api, |(name, _)| name).to_string()
+}
+
+/// Audio outputs as named on the command line and by the API.
+pub const AUDIO_OUTPUTS: [(&str, &str); 3] = [
+    ("speaker", "speaker"),
+    ("headphone", "headphone"),
+    ("audio-system", "audioSystem"),
+];
+
+/// External input sources alongside the serial protocol's input kinds they stand in for.
+const INPUT_SOURCES: [(&str, u8); 3] = [("composite", 0x02), ("component", 0x03), ("hdmi", 0x04)];
+
+pub struct Rest {
+    host: String,
+    psk: Option<String>,
+}
+
+impl Rest {
+    /// Takes `[PSK@]HOST[:PORT]`.  Nothing is sent until the first request.
+    pub fn new(address: &str) -> Rest {
+        let (psk, host) = backend::split_password(address);
+        Rest {
+            host: host.to_string(),
+            psk: psk.map(str::to_string),
+        }
+    }
+
+    /// Sends an HTTP POST and returns the response body.
+    fn post(&self, path: &str, headers: &[(&str, &str)], body: &str) -> Result<String, Error> {
+        let mut stream = backend::connect(&self.host, DEFAULT_PORT, TIMEOUT)?;
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            path,
+            self.host,
+            body.len()
+        );
+        if let Some(psk) = &self.psk {
+            request.push_str(&format!("X-Auth-PSK: {}\r\n", psk));
+        }
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        stream.write_all(request.as_bytes())?;
+
+        read_response(&mut stream)
+    }
+
+    /// Calls a JSON-RPC method, where `params` is the JSON for the single parameter (or empty
+    /// for none), and returns the `result` array.
+    pub fn call(
+        &self,
+        service: &str,
+        method: &str,
+        version: &str,
+        params: &str,
+    ) -> Result<json::Value, Error> {
+        let params = if params.is_empty() {
+            String::from("[]")
+        } else {
+            format!("[{}]", params)
+        };
+        let body = format!(
+            "{{\"method\":{},\"id\":1,\"params\":{},\"version\":{}}}",
+            json_string(method),
+            params,
+            json_string(version)
+        );
+        let headers = [("Content-Type", "application/json")];
+        let response = self.post(&format!("/sony/{}", service), &headers, &body)?;
+        let response = json::parse(&response).ok_or(Error::UnexpectedAnswer)?;
+        if let Some(error) = response.get("error") {
+            let message = error.index(1).and_then(json::Value::as_str).unwrap_or("");
+            let code = error.index(0).and_then(json::Value::as_f64).unwrap_or(0.0);
+            return Err(Error::Api(code as u32, message.to_string()));
+        }
+        match response.get("result") {
+            Some(result) => Ok(result.clone()),
+            None => Err(Error::UnexpectedAnswer),
+        }
+    }
+
+    /// Returns the first object in a method's result.
+    fn call_first(
+        &self,
+        service: &str,
+        method: &str,
+        version: &str,
+        params: &str,
+    ) -> Result<json::Value, Error> {
+        let result = self.call(service, method, version, params)?;
+        result.index(0).cloned().ok_or(Error::ShortResponse)
+    }
+
+    fn send_ircc(&self, code: &str) -> Result<(), Error> {
+        let body = format!(
+            "<?xml version=\"1.0\"?>\
+             <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+             s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+             <s:Body><u:X_SendIRCC xmlns:u=\"urn:schemas-sony-com:service:IRCC:1\">\
+             <IRCCCode>{}</IRCCCode></u:X_SendIRCC></s:Body></s:Envelope>",
+            code
+        );
+        let headers = [
+            ("Content-Type", "text/xml; charset=UTF-8"),
+            (
+                "SOAPACTION",
+                "\"urn:schemas-sony-com:service:IRCC:1#X_SendIRCC\"",
+            ),
+        ];
+        self.post("/sony/IRCC", &headers, &body)?;
+        Ok(())
+    }
+
+    /// An output's entry from `getVolumeInformation`.  Some models report the speaker under
+    /// another name, so it falls back to the first entry.
+    fn volume_information(&self, target: &str) -> Result<json::Value, Error> {
+        let result = self.call("audio", "getVolumeInformation", "1.0", "")?;
+        let outputs = result
+            .index(0)
+            .and_then(json::Value::as_array)
+            .ok_or(Error::ShortResponse)?;
+        let output = outputs
+            .iter()
+            .find(|o| o.get("target").and_then(json::Value::as_str) == Some(target));
+        match output {
+            Some(output) => Ok(output.clone()),
+            None if target == "speaker" => outputs.first().cloned().ok_or(Error::ShortResponse),
+            None => Err(Error::Unsupported("this output")),
+        }
+    }
+
+    /// The `mode` of the first object in a method's result.
+    fn get_mode(&self, method: &str) -> Result<String, Error> {
+        let result = self.call_first("system", method, "1.0", "")?;
+        let mode = result.get("mode").and_then(json::Value::as_str);
+        mode.map(str::to_string).ok_or(Error::ShortResponse)
+    }
+
+    fn set_volume(&self, target: &str, volume: &str) -> Result<(), Error> {
+        let params = format!(
+            "{{\"target\":{},\"volume\":{}}}",
+            json_string(target),
+            json_string(volume)
+        );
+        self.call("audio", "setAudioVolume", "1.0", &params)?;
+        Ok(())
+    }
+}
+
+/// The input a content URI plays from.
+fn input_from_uri(uri: &str) -> Result<(u8, u8), Error> {
+    if uri.starts_with("tv:") {
+        return Ok((0x00, 0));
+    }
+    let input = match uri.strip_prefix("extInput:") {
+        Some(input) => input,
+        None => return Err(Error::Unsupported(APP_INPUT)),
+    };
+    let (source, port) = input.split_once("?port=").ok_or(Error::UnexpectedAnswer)?;
+    match INPUT_SOURCES.iter().find(|(s, _)| *s == source) {
+        Some((_, kind)) => Ok((*kind, port.parse().map_err(|_| Error::UnexpectedAnswer)?)),
+        None => Err(Error::UnexpectedAnswer),
+    }
+}
+
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Reads an HTTP response to the end and returns its body.
+fn read_response(stream: &mut impl Read) -> Result<String, Error> {
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let end = find(&response, b"\r\n\r\n").ok_or(Error::ShortResponse)?;
+    let head = String::from_utf8_lossy(&response[..end]);
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .ok_or(Error::UnexpectedHeader)?;
+    if status != "200" {
+        return Err(Error::Rest(format!("HTTP {}", status)));
+    }
+    let chunked = head.lines().any(|line| {
+        line.to_ascii_lowercase()
+            .starts_with("transfer-encoding: chunked")
+    });
+    let body = &response[end + 4..];
+    if chunked {
+        Ok(String::from_utf8_lossy(&dechunk(body)?).into_owned())
+    } else {
+        Ok(String::from_utf8_lossy(body).into_owned())
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Decodes a chunked body.  Chunk sizes count bytes, so a chunk can end partway through a
+/// UTF-8 character; the body is only decoded as text once it's put back together.
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut decoded = Vec::new();
+    loop {
+        let end = find(body, b"\r\n").ok_or(Error::ShortResponse)?;
+        let size = std::str::from_utf8(&body[..end])
+            .ok()
+            .and_then(|line| usize::from_str_radix(line.split(';').next()?.trim(), 16).ok())
+            .ok_or(Error::UnexpectedAnswer)?;
+        let rest = &body[end + 2..];
+        if size == 0 {
+            return Ok(decoded);
+        }
+        decoded.extend_from_slice(rest.get(..size).ok_or(Error::ShortResponse)?);
+        body = match rest[size..].strip_prefix(b"\r\n") {
+            Some(rest) => rest,
+            None if b"\r\n".starts_with(&rest[size..]) => return Err(Error::ShortResponse),
+            None => return Err(Error::UnexpectedAnswer),
+        };
+    }
+}
+
+impl Backend for Rest {
+    fn power_on(&mut self) -> Result<(), Error> {
+        self.call("system", "setPowerStatus", "1.0", "{\"status\":true}")?;
+        Ok(())
+    }
+
+    fn power_off(&mut self) -> Result<(), Error> {
+        self.call("system", "setPowerStatus", "1.0", "{\"status\":false}")?;
+        Ok(())
+    }
+
+    fn is_powered_on(&mut self) -> Result<bool, Error> {
+        let status = self.call_first("system", "getPowerStatus", "1.0", "")?;
+        match status.get("status").and_then(json::Value::as_str) {
+            Some("active") => Ok(true),
+            Some(_) => Ok(false),
+            None => Err(Error::ShortResponse),
+        }
+    }
+
+    fn volume_up(&mut self) -> Result<(), Error> {
+        self.set_volume("speaker", "+1")
+    }
+
+    fn volume_down(&mut self) -> Result<(), Error> {
+        self.set_volume("speaker", "-1")
+    }
+
+    fn volume_set(&mut self, volume: u8) -> Result<(), Error> {
+        self.set_volume("speaker", &volume.to_string())
+    }
+
+    fn get_volume(&mut self) -> Result<u8, Error> {
+        self.output_volume("speaker")
+    }
+
+    fn mute_set(&mut self, muted: bool) -> Result<(), Error> {
+        let params = format!("{{\"status\":{}}}", muted);
+        self.call("audio", "setAudioMute", "1.0", &params)?;
+        Ok(())
+    }
+
+    fn is_muted(&mut self) -> Result<bool, Error> {
+        let muted = self
+            .volume_information("speaker")?
+            .get("mute")
+            .and_then(json::Value::as_bool);
+        muted.ok_or(Error::ShortResponse)
+    }
+
+    /// The tuner has no URI of its own, only channels do, so it's selected with the TV key.
+    fn input_select(&mut self, kind: u8, number: u8) -> Result<(), Error> {
+        if kind == 0x00 {
+            return self.send_ircc(key_code("tv").expect("the tv key is built in"));
+        }
+        let source = INPUT_SOURCES
+            .iter()
+            .find(|(_, k)| *k == kind)
+            .map(|(source, _)| *source)
+            .ok_or(Error::Unsupported("this input type"))?;
+        let uri = format!("extInput:{}?port={}", source, number);
+        let params = format!("{{\"uri\":{}}}", json_string(&uri));
+        self.call("avContent", "setPlayContent", "1.0", &params)?;
+        Ok(())
+    }
+
+    /// Reports the tuner as `tv`.  Apps have no input, which is reported as unsupported so that
+    /// it's left out like any other setting the TV can't report, rather than taken for a
+    /// failure to reach the TV.
+    fn get_input(&mut self) -> Result<(u8, u8), Error> {
+        let playing = match self.call_first("avContent", "getPlayingContentInfo", "1.0", "") {
+            Ok(playing) => playing,
+            Err(Error::Api(ILLEGAL_STATE, _)) => return Err(Error::Unsupported(APP_INPUT)),
+            Err(e) => return Err(e),
+        };
+        let uri = playing
+            .get("uri")
+            .and_then(json::Value::as_str)
+            .ok_or(Error::ShortResponse)?;
+        input_from_uri(uri)
+    }
+
+    fn send_code(&mut self, code: &str) -> Result<(), Error> {
+        self.send_ircc(code)
+    }
+
+    fn apps(&mut self) -> Result<Vec<App>, Error> {
+        let result = self.call("appControl", "getApplicationList", "1.0", "")?;
+        let apps = result
+            .index(0)
+            .and_then(json::Value::as_array)
+            .ok_or(Error::ShortResponse)?;
+        Ok(apps
+            .iter()
+            .filter_map(|app| {
+                Some(App {
+                    title: app.get("title")?.as_str()?.to_string(),
+                    uri: app.get("uri")?.as_str()?.to_string(),
+                })
+            })
+            .collect())
+    }
+
+    fn launch_app(&mut self, uri: &str) -> Result<(), Error> {
+        let params = format!("{{\"uri\":{}}}", json_string(uri));
+        self.call("appControl", "setActiveApp", "1.0", &params)?;
+        Ok(())
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), Error> {
+        self.call("appControl", "setTextForm", "1.0", &json_string(text))?;
+        Ok(())
+    }
+
+    /// Asks for version 1.0 of both methods, which every TV answers.  Not every firmware
+    /// includes its own version in the 1.0 answer, so fields that are missing are left out.
+    fn info(&mut self) -> Result<Vec<(&'static str, String)>, Error> {
+        let system = self.call_first("system", "getSystemInformation", "1.0", "")?;
+        let interface = self.call_first("system", "getInterfaceInformation", "1.0", "")?;
+        let fields = [
+            ("Model", &system, "model"),
+            ("Serial", &system, "serial"),
+            ("MAC address", &system, "macAddr"),
+            ("Firmware", &system, "fwVersion"),
+            ("API version", &interface, "interfaceVersion"),
+        ];
+        Ok(fields
+            .iter()
+            .filter_map(|(label, object, key)| {
+                let value = object.get(key)?.as_str()?;
+                Some((*label, value.to_string()))
+            })
+            .collect())
+    }
+
+    fn power_saving(&mut self) -> Result<String, Error> {
+        Ok(cli_name(
+            &POWER_SAVING_MODES,
+            &self.get_mode("getPowerSavingMode")?,
+        ))
+    }
+
+    fn set_power_saving(&mut self, mode: &str) -> Result<(), Error> {
+        let params = format!(
+            "{{\"mode\":{}}}",
+            json_string(api_name(&POWER_SAVING_MODES, mode)?)
+        );
+        self.call("system", "setPowerSavingMode", "1.0", &params)?;
+        Ok(())
+    }
+
+    fn led_mode(&mut self) -> Result<String, Error> {
+        Ok(cli_name(
+            &LED_MODES,
+            &self.get_mode("getLEDIndicatorStatus")?,
+        ))
+    }
+
+    fn set_led_mode(&mut self, mode: &str) -> Result<(), Error> {
+        let params = format!("{{\"mode\":{}}}", json_string(api_name(&LED_MODES, mode)?));
+        self.call("system", "setLEDIndicatorStatus", "1.1", &params)?;
+        Ok(())
+    }
+
+    fn audio_output(&mut self) -> Result<String, Error> {
+        let params = "{\"target\":\"outputTerminal\"}";
+        let result = self.call("audio", "getSoundSettings", "1.1", params)?;
+        let output = result
+            .index(0)
+            .and_then(|settings| settings.index(0))
+            .and_then(|setting| setting.get("currentValue"))
+            .and_then(json::Value::as_str)
+            .ok_or(Error::ShortResponse)?;
+        Ok(cli_name(&AUDIO_OUTPUTS, output))
+    }
+
+    fn set_audio_output(&mut self, output: &str) -> Result<(), Error> {
+        let params = format!(
+            "{{\"settings\":[{{\"target\":\"outputTerminal\",\"value\":{}}}]}}",
+            json_string(api_name(&AUDIO_OUTPUTS, output)?)
+        );
+        self.call("audio", "setSoundSettings", "1.1", &params)?;
+        Ok(())
+    }
+
+    fn output_volume(&mut self, output: &str) -> Result<u8, Error> {
+        let information = self.volume_information(output)?;
+        let volume = information.get("volume").and_then(json::Value::as_f64);
+        volume.map(|v| v as u8).ok_or(Error::ShortResponse)
+    }
+
+    fn set_output_volume(&mut self, output: &str, volume: u8) -> Result<(), Error> {
+        self.set_volume(output, &volume.to_string())
+    }
+
+    /// Apps don't report what they are playing, so only the fact that one is in the foreground
+    /// can be shown.
+    fn playing(&mut self) -> Result<Vec<(&'static str, String)>, Error> {
+        let playing = match self.call_first("avContent", "getPlayingContentInfo", "1.0", "") {
+            Ok(playing) => playing,
+            Err(Error::Api(ILLEGAL_STATE, _)) => return Ok(vec![("Source", String::from("app"))]),
+            Err(e) => return Err(e),
+        };
+        let fields = [
+            ("Source", "source"),
+            ("Title", "title"),
+            ("Channel", "dispNum"),
+            ("Program", "programTitle"),
+            ("Started", "startDateTime"),
+            ("URI", "uri"),
+        ];
+        Ok(fields
+            .iter()
+            .filter_map(|(label, key)| {
+                let value = playing.get(key)?.as_str()?;
+                Some((*label, value.to_string()))
+            })
+            .filter(|(_, value)| !value.is_empty())
+            .collect())
+    }
+
+    /// Opens the page in the built-in HTML5 runtime that professional displays provide for
+    /// signage.
+    fn open_url(&mut self, url: &str) -> Result<(), Error> {
+        let uri = format!("localapp://webappruntime?url={}", percent_encode(url));
+        self.launch_app(&uri)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    /// Hands out a response a few bytes at a time, like a slow connection.
+    struct Trickle<'a>(&'a [u8], usize);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.1.min(buf.len()).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    fn response(text: impl AsRef<[u8]>) -> Result<String, Error> {
+        let text = text.as_ref();
+        let mut result = None;
+        for step in 1..=text.len() {
+            let body = read_response(&mut Trickle(text, step));
+            if let (Some(Ok(previous)), Ok(body)) = (&result, &body) {
+                assert_eq!(previous, body, "reading {} bytes at a time", step);
+            }
+            result = Some(body);
+        }
+        result.unwrap()
+    }
+
+    #[test]
+    fn plain_body() {
+        let body = response("HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n{\"id\": 1}\r\n");
+        assert_eq!(body.unwrap(), "{\"id\": 1}\r\n");
+    }
+
+    #[test]
+    fn chunked_body() {
+        let body = response(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             4\r\n{\"id\r\n6;name=value\r\n\": 1}\n\r\nA\r\n0123456789\r\n0\r\n\r\n",
+        );
+        assert_eq!(body.unwrap(), "{\"id\": 1}\n0123456789");
+    }
+
+    #[test]
+    fn chunk_splits_a_character() {
+        // "é" is two bytes, with one in each chunk.
+        let body = response(
+            b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n2\r\nA\xc3\r\n2\r\n\xa9B\r\n0\r\n\r\n",
+        );
+        assert_eq!(body.unwrap(), "AéB");
+    }
+
+    #[test]
+    fn bad_chunks() {
+        let head = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+        for (chunks, short) in [
+            ("", true),
+            ("5\r\nabc", true),
+            ("3\r\nabc", true),
+            ("3\r\nabc\r", true),
+            ("3\r\nabcd\r\n0\r\n\r\n", false),
+            ("x\r\nabc\r\n0\r\n\r\n", false),
+        ] {
+            match (response(format!("{}{}", head, chunks)), short) {
+                (Err(Error::ShortResponse), true) | (Err(Error::UnexpectedAnswer), false) => {}
+                (result, _) => panic!("{:?} gave {:?}", chunks, result.map_err(|e| e.to_string())),
+            }
+        }
+    }
+
+    #[test]
+    fn status_line() {
+        match response("HTTP/1.1 403 Forbidden\r\n\r\n") {
+            Err(Error::Rest(message)) => assert_eq!(message, "HTTP 403"),
+            result => panic!("{:?}", result.map_err(|e| e.to_string())),
+        }
+        assert!(matches!(
+            response("HTTP/1.1 200 OK\r\n"),
+            Err(Error::ShortResponse)
+        ));
+        assert!(matches!(response("\r\n\r\n"), Err(Error::UnexpectedHeader)));
+    }
+
+    #[test]
+    fn inputs_from_uris() {
+        assert!(matches!(
+            input_from_uri("tv:dvbt?trip=1.2.3"),
+            Ok((0x00, 0))
+        ));
+        assert!(matches!(
+            input_from_uri("extInput:hdmi?port=2"),
+            Ok((0x04, 2))
+        ));
+        assert!(matches!(
+            input_from_uri("extInput:component?port=1"),
+            Ok((0x03, 1))
+        ));
+        // An app has no input, which is left out of status rather than failing it.
+        assert!(matches!(
+            input_from_uri("com.sony.dtv.com.netflix.ninja.MainActivity"),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            input_from_uri("extInput:hdmi"),
+            Err(Error::UnexpectedAnswer)
+        ));
+        assert!(matches!(
+            input_from_uri("extInput:hdmi?port=x"),
+            Err(Error::UnexpectedAnswer)
+        ));
+    }
+}
@@ -0,0 +1,285 @@
+//! Sony's RS-232C control protocol, spoken over a serial port.
+
+use std::io::{Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backend::Backend;
+use crate::ssh::SshPort;
+use crate::Error;
+
+const CONTROL_REQUEST: u8 = 0x8c;
+const QUERY_REQUEST: u8 = 0x83;
+const CATEGORY: u8 = 0x00;
+pub const POWER_FUNCTION: u8 = 0x00;
+pub const VOLUME_CONTROL_FUNCTION: u8 = 0x05;
+pub const INPUT_SELECT_FUNCTION: u8 = 0x02;
+pub const MUTING_FUNCTION: u8 = 0x06;
+pub const CONTRAST_FUNCTION: u8 = 0x23;
+pub const BRIGHTNESS_FUNCTION: u8 = 0x24;
+pub const COLOR_FUNCTION: u8 = 0x25;
+pub const SHARPNESS_FUNCTION: u8 = 0x28;
+
+/// The picture adjustments, by the names `picture` takes.  Sony's documentation calls contrast
+/// "Picture".
+pub const PICTURE_FUNCTIONS: [(&str, u8); 4] = [
+    ("brightness", BRIGHTNESS_FUNCTION),
+    ("contrast", CONTRAST_FUNCTION),
+    ("color", COLOR_FUNCTION),
+    ("sharpness", SHARPNESS_FUNCTION),
+];
+
+const RESPONSE_HEADER: u8 = 0x70;
+const RESPONSE_ANSWER: u8 = 0x00;
+
+/// Fills in a frame's last byte with the checksum of the bytes before it, so frames can be
+/// written out as constants with a placeholder `0` at the end.
+const fn frame<const N: usize>(mut bytes: [u8; N]) -> [u8; N] {
+    bytes[N - 1] = checksum(bytes.split_at(N - 1).0);
+    bytes
+}
+
+const fn control(function: u8, data: [u8; 2]) -> [u8; 7] {
+    frame([
+        CONTROL_REQUEST,
+        CATEGORY,
+        function,
+        0x03,
+        data[0],
+        data[1],
+        0,
+    ])
+}
+
+const fn query(function: u8) -> [u8; 6] {
+    frame([QUERY_REQUEST, CATEGORY, function, 0xff, 0xff, 0])
+}
+
+const POWER_ON: [u8; 6] = frame([CONTROL_REQUEST, CATEGORY, POWER_FUNCTION, 0x02, 0x01, 0]);
+const POWER_OFF: [u8; 6] = frame([CONTROL_REQUEST, CATEGORY, POWER_FUNCTION, 0x02, 0x00, 0]);
+const VOLUME_UP: [u8; 7] = control(VOLUME_CONTROL_FUNCTION, [0x00, 0x00]);
+const VOLUME_DOWN: [u8; 7] = control(VOLUME_CONTROL_FUNCTION, [0x00, 0x01]);
+const POWER_QUERY: [u8; 6] = query(POWER_FUNCTION);
+const VOLUME_QUERY: [u8; 6] = query(VOLUME_CONTROL_FUNCTION);
+const MUTING_QUERY: [u8; 6] = query(MUTING_FUNCTION);
+const INPUT_QUERY: [u8; 6] = query(INPUT_SELECT_FUNCTION);
+
+// Frames as printed in Sony's protocol documentation.
+const _: () = assert!(matches!(POWER_ON, [0x8c, 0x00, 0x00, 0x02, 0x01, 0x8f]));
+const _: () = assert!(matches!(POWER_OFF, [0x8c, 0x00, 0x00, 0x02, 0x00, 0x8e]));
+const _: () = assert!(matches!(POWER_QUERY, [0x83, 0x00, 0x00, 0xff, 0xff, 0x81]));
+const _: () = assert!(matches!(
+    control(VOLUME_CONTROL_FUNCTION, [0x01, 0x14]),
+    [0x8c, 0x00, 0x05, 0x03, 0x01, 0x14, 0xa9]
+));
+
+/// The data of a query response.  Its length byte counts the checksum too, so there are at
+/// most 254 bytes.
+struct Response {
+    buf: [u8; 255],
+    len: usize,
+}
+
+impl Response {
+    fn data(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// How long the TV stays busy after switching power or input, answering anything sent meanwhile
+/// with an error.
+const POWER_BUSY: Duration = Duration::from_secs(2);
+const INPUT_BUSY: Duration = Duration::from_secs(1);
+
+/// Where frames are written and responses read: a local serial port, or one reached over SSH.
+trait Port: Read + Write {}
+
+impl<T: Read + Write> Port for T {}
+
+pub struct Serial {
+    port: Box<dyn Port>,
+    /// The end of the busy window after the last power or input change.
+    busy_until: Option<Instant>,
+}
+
+impl Serial {
+    pub fn open(device: &str) -> Result<Serial, Error> {
+        let port: Box<dyn Port> = match device.strip_prefix("ssh://") {
+            Some(url) => Box::new(SshPort::open(url)?),
+            None => Box::new(
+                serialport::new(port_name(device), 9600)
+                    .timeout(Duration::from_millis(500))
+                    .open()
+                    .map_err(Error::Open)?,
+            ),
+        };
+        Ok(Serial {
+            port,
+            busy_until: None,
+        })
+    }
+
+    /// Writes a complete frame, checksum included, and reads the response.  Waits out any busy
+    /// window first.
+    fn write_command(&mut self, frame: &[u8]) -> Result<Response, Error> {
+        if let Some(busy_until) = self.busy_until.take() {
+            thread::sleep(busy_until.saturating_duration_since(Instant::now()));
+        }
+        self.port.write_all(frame)?;
+
+        let mut header = [0; 3];
+        self.port.read_exact(&mut header)?;
+
+        if header[0] != RESPONSE_HEADER {
+            return Err(Error::UnexpectedHeader);
+        }
+        if header[1] != RESPONSE_ANSWER {
+            return Err(Error::UnexpectedAnswer);
+        }
+        let mut response = Response {
+            buf: [0; 255],
+            len: 0,
+        };
+        if frame.first() == Some(&QUERY_REQUEST) {
+            let len = header[2] as usize;
+            self.port.read_exact(&mut response.buf[..len])?;
+            let (resp_checksum, data) = response.buf[..len]
+                .split_last()
+                .ok_or(Error::InvalidChecksum)?;
+            if *resp_checksum != checksum(&header).wrapping_add(checksum(data)) {
+                return Err(Error::InvalidChecksum);
+            }
+            response.len = data.len();
+        } else if header[2] != checksum(&header[..2]) {
+            return Err(Error::InvalidChecksum);
+        }
+        Ok(response)
+    }
+}
+
+/// The names of the serial ports on this machine, or none if they can't be listed.
+pub fn available_ports() -> Vec<String> {
+    serialport::available_ports()
+        .map(|ports| ports.into_iter().map(|p| p.port_name).collect())
+        .unwrap_or_default()
+}
+
+/// serialport adds the `\\.\` device namespace prefix itself on Windows, which is needed for
+/// `COM10` and up, so one given by the user is stripped rather than doubled.
+#[cfg(windows)]
+fn port_name(device: &str) -> String {
+    let name = device.strip_prefix(r"\\.\").unwrap_or(device);
+    match name.get(..3) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("com") => name.to_ascii_uppercase(),
+        _ => name.to_string(),
+    }
+}
+
+/// On macOS, opening a `/dev/tty.*` callin device blocks until carrier detect is raised, which
+/// an adapter wired to a TV never does, so the matching `/dev/cu.*` callout device is used.
+#[cfg(target_os = "macos")]
+fn port_name(device: &str) -> String {
+    match device.strip_prefix("/dev/tty.") {
+        Some(name) => format!("/dev/cu.{}", name),
+        None => device.to_string(),
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn port_name(device: &str) -> String {
+    device.to_string()
+}
+
+/// The sum of the bytes, modulo 256.
+const fn checksum(bytes: &[u8]) -> u8 {
+    let mut sum: u8 = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        sum = sum.wrapping_add(bytes[i]);
+        i += 1;
+    }
+    sum
+}
+
+impl Backend for Serial {
+    fn power_on(&mut self) -> Result<(), Error> {
+        self.write_command(&POWER_ON)?;
+        self.busy_until = Some(Instant::now() + POWER_BUSY);
+        Ok(())
+    }
+
+    fn power_off(&mut self) -> Result<(), Error> {
+        self.write_command(&POWER_OFF)?;
+        self.busy_until = Some(Instant::now() + POWER_BUSY);
+        Ok(())
+    }
+
+    fn is_powered_on(&mut self) -> Result<bool, Error> {
+        let response = self.write_command(&POWER_QUERY)?;
+        Ok(*response.data().first().ok_or(Error::ShortResponse)? == 1)
+    }
+
+    fn volume_up(&mut self) -> Result<(), Error> {
+        self.write_command(&VOLUME_UP)?;
+        Ok(())
+    }
+
+    fn volume_down(&mut self) -> Result<(), Error> {
+        self.write_command(&VOLUME_DOWN)?;
+        Ok(())
+    }
+
+    fn volume_set(&mut self, volume: u8) -> Result<(), Error> {
+        self.write_command(&control(VOLUME_CONTROL_FUNCTION, [0x01, volume]))?;
+        Ok(())
+    }
+
+    fn get_volume(&mut self) -> Result<u8, Error> {
+        let response = self.write_command(&VOLUME_QUERY)?;
+        response.data().last().copied().ok_or(Error::ShortResponse)
+    }
+
+    fn picture(&mut self, function: u8) -> Result<u8, Error> {
+        let response = self.write_command(&query(function))?;
+        response.data().last().copied().ok_or(Error::ShortResponse)
+    }
+
+    fn set_picture(&mut self, function: u8, value: u8) -> Result<(), Error> {
+        self.write_command(&control(function, [0x01, value]))?;
+        Ok(())
+    }
+
+    fn mute_set(&mut self, muted: bool) -> Result<(), Error> {
+        self.write_command(&control(MUTING_FUNCTION, [0x01, muted as u8]))?;
+        Ok(())
+    }
+
+    fn is_muted(&mut self) -> Result<bool, Error> {
+        let response = self.write_command(&MUTING_QUERY)?;
+        Ok(*response.data().first().ok_or(Error::ShortResponse)? == 1)
+    }
+
+    fn input_select(&mut self, kind: u8, number: u8) -> Result<(), Error> {
+        if kind == 0x00 {
+            return Err(Error::Unsupported("selecting the tuner"));
+        }
+        self.write_command(&control(INPUT_SELECT_FUNCTION, [kind, number]))?;
+        self.busy_until = Some(Instant::now() + INPUT_BUSY);
+        Ok(())
+    }
+
+    fn get_input(&mut self) -> Result<(u8, u8), Error> {
+        let response = self.write_command(&INPUT_QUERY)?;
+        match response.data() {
+            [kind, number, ..] => Ok((*kind, *number)),
+            _ => Err(Error::ShortResponse),
+        }
+    }
+
+    /// Custom frames come from the config file, so unlike the built-in ones they are only known
+    /// at runtime and have their checksum appended here.
+    fn send_frame(&mut self, frame: &[u8]) -> Result<Vec<u8>, Error> {
+        let frame = [frame, &[checksum(frame)]].concat();
+        Ok(self.write_command(&frame)?.data().to_vec())
+    }
+}
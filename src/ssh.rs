@@ -0,0 +1,210 @@
+//! Serial ports on another machine, reached with `ssh://[USER@]HOST[:PORT]/PATH` device URLs.
+//! Frames are shuttled through the system's `ssh` client to a `stty` and `cat` pair on the
+//! remote end, so the remote machine needs nothing installed beyond a POSIX shell, and login
+//! has to work without a password prompt.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use crate::Error;
+
+/// What the remote end prints once the port is set up, before any data from the TV.
+const READY: &[u8] = b"ready\n";
+
+/// How long to wait for the connection and login.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for a response: the serial port's timeout, plus room for the round trip.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct SshPort {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    output: Receiver<Vec<u8>>,
+    pending: VecDeque<u8>,
+    timeout: Duration,
+}
+
+impl SshPort {
+    /// Connects to the port named by an `ssh://` URL, without the scheme.
+    pub fn open(url: &str) -> Result<SshPort, Error> {
+        let (destination, port, path) =
+            parse_url(url).ok_or_else(|| invalid("expected ssh://[USER@]HOST[:PORT]/PATH"))?;
+        let mut command = Command::new("ssh");
+        command.args(["-T", "-o", "BatchMode=yes"]);
+        command.arg(format!("-oConnectTimeout={}", CONNECT_TIMEOUT.as_secs()));
+        if let Some(port) = port {
+            command.args(["-p", port]);
+        }
+        let path = quote(path);
+        let script = format!(
+            "stty 9600 cs8 -cstopb -parenb raw -echo < {path} && echo ready && \
+             {{ cat < {path} & cat > {path}; kill $!; }}",
+            path = path
+        );
+        let mut child = command
+            .args(["--", &destination, &script])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(Error::Connect)?;
+        let stdin = child.stdin.take();
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+
+        // Reads are done on another thread so that they can time out like a serial port's.
+        let (sender, output) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 256];
+            while let Ok(n @ 1..) = stdout.read(&mut buf) {
+                if sender.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut port = SshPort {
+            child,
+            stdin,
+            output,
+            pending: VecDeque::new(),
+            timeout: CONNECT_TIMEOUT,
+        };
+        let mut ready = [0; READY.len()];
+        port.read_exact(&mut ready).map_err(Error::Connect)?;
+        if ready != READY {
+            return Err(invalid("unexpected output from the remote shell"));
+        }
+        port.timeout = TIMEOUT;
+        Ok(port)
+    }
+}
+
+/// Splits a URL, without the scheme, into the destination for `ssh`, the port if one is given,
+/// and the path of the serial port.  An IPv6 address needs brackets to be given a port.
+fn parse_url(url: &str) -> Option<(String, Option<&str>, &str)> {
+    let (authority, path) = match url.find('/') {
+        Some(i) if i > 0 => url.split_at(i),
+        _ => return None,
+    };
+    let (destination, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') || host.ends_with(']') => {
+            if !port.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            (host, Some(port))
+        }
+        _ => (authority, None),
+    };
+    let destination = destination.replace(['[', ']'], "");
+    if destination.is_empty() || port == Some("") {
+        return None;
+    }
+    Some((destination, port, path))
+}
+
+fn invalid(message: &str) -> Error {
+    Error::Connect(io::Error::new(io::ErrorKind::InvalidInput, message))
+}
+
+/// Quotes a path for the remote shell.
+fn quote(path: &str) -> String {
+    format!("'{}'", path.replace('\'', "'\\''"))
+}
+
+impl Read for SshPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.output.recv_timeout(self.timeout) {
+                Ok(data) => self.pending.extend(data),
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the SSH connection closed",
+                    ))
+                }
+            }
+        }
+        let n = buf.len().min(self.pending.len());
+        for (byte, pending) in buf.iter_mut().zip(self.pending.drain(..n)) {
+            *byte = pending;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for SshPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.stdin {
+            Some(stdin) => stdin.write(buf),
+            None => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.stdin {
+            Some(stdin) => stdin.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Closing stdin ends the remote `cat`s, which lets `ssh` exit by itself; it's only killed if
+/// it doesn't in good time.
+impl Drop for SshPort {
+    fn drop(&mut self) {
+        drop(self.stdin.take());
+        for _ in 0..10 {
+            if !matches!(self.child.try_wait(), Ok(None)) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls() {
+        let url = |destination: &str, port, path| Some((destination.to_string(), port, path));
+        assert_eq!(parse_url("pi/dev/ttyUSB0"), url("pi", None, "/dev/ttyUSB0"));
+        assert_eq!(
+            parse_url("me@pi.local:2222/dev/ttyUSB0"),
+            url("me@pi.local", Some("2222"), "/dev/ttyUSB0")
+        );
+        assert_eq!(
+            parse_url("me@fe80::1/dev/ttyS0"),
+            url("me@fe80::1", None, "/dev/ttyS0")
+        );
+        assert_eq!(
+            parse_url("me@[fe80::1]:2222/dev/ttyS0"),
+            url("me@fe80::1", Some("2222"), "/dev/ttyS0")
+        );
+        for url in [
+            "",
+            "pi",
+            "/dev/ttyUSB0",
+            "pi:/dev/ttyUSB0",
+            ":22/dev/ttyUSB0",
+            "pi:ssh/dev/ttyS0",
+        ] {
+            assert_eq!(parse_url(url), None, "{:?}", url);
+        }
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote("/dev/ttyUSB0"), "'/dev/ttyUSB0'");
+        assert_eq!(quote("/dev/it's $HOME"), "'/dev/it'\\''s $HOME'");
+    }
+}